@@ -1,11 +1,15 @@
 use crate::{
-    bisq::SequencedMessageHash,
+    bisq::{constants::Capability, SequencedMessageHash},
+    daemon::{GetResyncStats, ResyncStats},
     domain::{
         currency::{self, Currency},
         market::{self, Market},
         offer::{message::GetOpenOffers, OfferBook, OfferDirection, OpenOffer},
         statistics::*,
     },
+    p2p::message::{
+        BannedPeer, ConnectionReport, GetBannedPeers, GetNetworkInfo, KnownPeer, NetworkInfo,
+    },
     prelude::*,
 };
 use actix_web::{web, Error, HttpResponse};
@@ -54,31 +58,52 @@ pub struct GraphQLContextWrapper {
     #[cfg(feature = "statistics")]
     pub stats_cache: StatsCache,
     pub offer_book: Addr<OfferBook>,
+    pub banned_peers: Recipient<GetBannedPeers>,
+    pub network_info: Recipient<GetNetworkInfo>,
+    pub resync_stats: Recipient<GetResyncStats>,
 }
 impl GraphQLContextWrapper {
     #[cfg(feature = "statistics")]
     pub fn get(&self) -> impl Future<Item = GraphQLContext, Error = Error> {
-        Future::join(
+        let network_info = self.network_info.clone();
+        let resync_stats = self.resync_stats.clone();
+        Future::join3(
             self.stats_cache.inner().map_err(Error::from),
             self.offer_book.send(GetOpenOffers).map_err(Error::from),
+            self.banned_peers.send(GetBannedPeers).map_err(Error::from),
         )
-        .map(|(stats_cache, open_offers)| GraphQLContext {
+        .map(|(stats_cache, open_offers, banned_peers)| GraphQLContext {
             stats_cache,
             open_offers,
+            banned_peers,
+            network_info,
+            resync_stats,
         })
     }
     #[cfg(not(feature = "statistics"))]
     pub fn get(&self) -> impl Future<Item = GraphQLContext, Error = Error> {
-        self.offer_book
-            .send(GetOpenOffers)
-            .map_err(Error::from)
-            .map(|open_offers| GraphQLContext { open_offers })
+        let network_info = self.network_info.clone();
+        let resync_stats = self.resync_stats.clone();
+        Future::join(
+            self.offer_book.send(GetOpenOffers).map_err(Error::from),
+            self.banned_peers.send(GetBannedPeers).map_err(Error::from),
+        )
+        .map(|(open_offers, banned_peers)| GraphQLContext {
+            open_offers,
+            banned_peers,
+            network_info,
+            resync_stats,
+        })
     }
 }
 pub struct GraphQLContext {
     #[cfg(feature = "statistics")]
     stats_cache: locks::RwLockReadGuard<StatsCacheInner>,
     open_offers: Arc<HashMap<SequencedMessageHash, OpenOffer>>,
+    banned_peers: Vec<BannedPeer>,
+    // Collecting it asks every connection for its stats so only do so when it is queried
+    network_info: Recipient<GetNetworkInfo>,
+    resync_stats: Recipient<GetResyncStats>,
 }
 impl juniper::Context for GraphQLContext {}
 
@@ -245,6 +270,42 @@ impl QueryFields for Query {
             .cloned()
             .collect())
     }
+
+    fn field_banned_peers(
+        &self,
+        executor: &juniper::Executor<'_, GraphQLContext>,
+        _trail: &QueryTrail<'_, BannedPeer, juniper_from_schema::Walked>,
+    ) -> FieldResult<Vec<BannedPeer>> {
+        Ok(executor.context().banned_peers.clone())
+    }
+
+    fn field_network(
+        &self,
+        executor: &juniper::Executor<'_, GraphQLContext>,
+        _trail: &QueryTrail<'_, NetworkInfo, juniper_from_schema::Walked>,
+    ) -> FieldResult<NetworkInfo> {
+        // Runs on a blocking thread so waiting doesn't stall the actors
+        executor
+            .context()
+            .network_info
+            .send(GetNetworkInfo)
+            .wait()
+            .map_err(|e| format!("Couldn't get network info: {:?}", e))?
+            .map_err(|e| format!("Couldn't get network info: {:?}", e).into())
+    }
+
+    fn field_resync(
+        &self,
+        executor: &juniper::Executor<'_, GraphQLContext>,
+        _trail: &QueryTrail<'_, ResyncStats, juniper_from_schema::Walked>,
+    ) -> FieldResult<ResyncStats> {
+        executor
+            .context()
+            .resync_stats
+            .send(GetResyncStats)
+            .wait()
+            .map_err(|e| format!("Couldn't get resync stats: {:?}", e).into())
+    }
 }
 
 const TARGET_PRECISION: u32 = 8;
@@ -526,6 +587,152 @@ impl OpenOfferFields for OpenOffer {
     }
 }
 
+impl BannedPeerFields for BannedPeer {
+    fn field_node_address(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<String> {
+        Ok(self.node_address.to_string())
+    }
+    fn field_banned_until(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<UnixMillis> {
+        Ok(self.banned_until.into())
+    }
+}
+
+impl ResyncStatsFields for ResyncStats {
+    fn field_rounds(&self, _executor: &juniper::Executor<'_, GraphQLContext>) -> FieldResult<i32> {
+        Ok(self.rounds as i32)
+    }
+    fn field_recovered_entries(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<i32> {
+        Ok(self.recovered_entries as i32)
+    }
+    fn field_recovered_payloads(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<i32> {
+        Ok(self.recovered_payloads as i32)
+    }
+    fn field_last_resync(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Option<UnixMillis>> {
+        Ok(self.last_resync.map(UnixMillis::from))
+    }
+}
+
+impl NetworkInfoFields for NetworkInfo {
+    fn field_node_address(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Option<String>> {
+        Ok(self.local_addr.as_ref().map(ToString::to_string))
+    }
+    fn field_bootstrap_seeds(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Vec<String>> {
+        Ok(self
+            .bootstrap_seeds
+            .iter()
+            .map(ToString::to_string)
+            .collect())
+    }
+    fn field_connections(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+        _trail: &QueryTrail<'_, ConnectionReport, juniper_from_schema::Walked>,
+    ) -> FieldResult<&Vec<ConnectionReport>> {
+        Ok(&self.connections)
+    }
+    fn field_peers(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+        _trail: &QueryTrail<'_, KnownPeer, juniper_from_schema::Walked>,
+    ) -> FieldResult<&Vec<KnownPeer>> {
+        Ok(&self.peers)
+    }
+}
+
+impl ConnectionReportFields for ConnectionReport {
+    fn field_id(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<juniper::ID> {
+        Ok(juniper::ID::new(self.id.to_string()))
+    }
+    fn field_direction(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<ConnectionDirection> {
+        Ok(if self.inbound {
+            ConnectionDirection::Inbound
+        } else {
+            ConnectionDirection::Outbound
+        })
+    }
+    fn field_node_address(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Option<String>> {
+        Ok(self.addr.as_ref().map(ToString::to_string))
+    }
+    fn field_round_trip_time(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Option<i32>> {
+        Ok(self.round_trip_time.map(|rtt| rtt.as_millis() as i32))
+    }
+    fn field_last_activity(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Option<UnixMillis>> {
+        Ok(self.stats.last_received_at.map(UnixMillis::from))
+    }
+}
+
+impl KnownPeerFields for KnownPeer {
+    fn field_node_address(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<String> {
+        Ok(self.addr.to_string())
+    }
+    fn field_reported_alive_at(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<UnixMillis> {
+        Ok(self.reported_alive_at.into())
+    }
+    fn field_reported_capabilities(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Option<Vec<String>>> {
+        Ok(self
+            .reported_capabilities
+            .as_ref()
+            .map(|c| capability_names(c)))
+    }
+    fn field_gossiped_capabilities(
+        &self,
+        _executor: &juniper::Executor<'_, GraphQLContext>,
+    ) -> FieldResult<Option<Vec<String>>> {
+        Ok(self
+            .gossiped_capabilities
+            .as_ref()
+            .map(|c| capability_names(c)))
+    }
+}
+
+fn capability_names(capabilities: &[Capability]) -> Vec<String> {
+    capabilities.iter().map(|c| format!("{:?}", c)).collect()
+}
+
 impl TickerFields for Ticker {
     fn field_market_pair(
         &self,
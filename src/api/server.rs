@@ -1,6 +1,8 @@
 use super::graphql::*;
 use crate::{
+    daemon::GetResyncStats,
     domain::{offer::OfferBook, statistics::*},
+    p2p::message::{GetBannedPeers, GetNetworkInfo},
     prelude::*,
 };
 use actix_web::{middleware::Logger, web, App, HttpServer, Result};
@@ -11,11 +13,17 @@ pub fn listen(
     port: u16,
     offer_book: Addr<OfferBook>,
     stats_cache: Option<StatsCache>,
+    banned_peers: Recipient<GetBannedPeers>,
+    network_info: Recipient<GetNetworkInfo>,
+    resync_stats: Recipient<GetResyncStats>,
 ) -> Result<(), io::Error> {
     let gql_context = GraphQLContextWrapper {
         #[cfg(feature = "statistics")]
         stats_cache: stats_cache.unwrap(),
         offer_book,
+        banned_peers,
+        network_info,
+        resync_stats,
     };
     listen_with_context(port, gql_context)
 }
@@ -98,6 +98,18 @@ lazy_static! {
             .for_each(|c| vec.push(*c as i32));
         vec
     };
+    pub static ref SEED_NODE_CAPABILITIES: Vec<i32> = {
+        let mut vec = LOCAL_CAPABILITIES.clone();
+        vec.push(Capability::SeedNode as i32);
+        vec
+    };
+}
+pub fn local_capabilities(seed_node: bool) -> Vec<i32> {
+    if seed_node {
+        SEED_NODE_CAPABILITIES.clone()
+    } else {
+        LOCAL_CAPABILITIES.clone()
+    }
 }
 static SUPPORTED_CAPABILITIES: [Capability; 11] = [
     Capability::TradeStatistics,
@@ -154,6 +166,7 @@ impl TryFrom<i32> for Capability {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum CloseConnectionReason {
     SocketClosed,
     Reset,
@@ -71,51 +71,61 @@ impl From<&Message> for Option<CorrelationId> {
     }
 }
 
+pub fn is_response(msg: &Message) -> bool {
+    match msg {
+        Message::GetDataResponse(_)
+        | Message::GetPeersResponse(_)
+        | Message::Pong(_)
+        | Message::OfferAvailabilityResponse(_)
+        | Message::GetBlocksResponse(_)
+        | Message::GetDaoStateHashesResponse(_)
+        | Message::GetProposalStateHashesResponse(_)
+        | Message::GetBlindVoteStateHashesResponse(_) => true,
+        _ => false,
+    }
+}
+
 pub trait ResponseExtractor {
     type Response: Send;
-    fn extract(msg: Message) -> Self::Response;
+    fn extract(msg: Message) -> Option<Self::Response>;
 }
 impl ResponseExtractor for PreliminaryGetDataRequest {
     type Response = GetDataResponse;
 
-    fn extract(msg: Message) -> Self::Response {
+    fn extract(msg: Message) -> Option<Self::Response> {
         match msg {
             Message::GetDataResponse(response) => Some(response),
             _ => None,
         }
-        .expect("Msg was not the extpected response type")
     }
 }
 impl ResponseExtractor for GetUpdatedDataRequest {
     type Response = GetDataResponse;
 
-    fn extract(msg: Message) -> Self::Response {
+    fn extract(msg: Message) -> Option<Self::Response> {
         match msg {
             Message::GetDataResponse(response) => Some(response),
             _ => None,
         }
-        .expect("Msg was not the extpected response type")
     }
 }
 impl ResponseExtractor for GetPeersRequest {
     type Response = GetPeersResponse;
 
-    fn extract(msg: Message) -> Self::Response {
+    fn extract(msg: Message) -> Option<Self::Response> {
         match msg {
             Message::GetPeersResponse(response) => Some(response),
             _ => None,
         }
-        .expect("Msg was not the extpected response type")
     }
 }
 impl ResponseExtractor for Ping {
     type Response = Pong;
 
-    fn extract(msg: Message) -> Self::Response {
+    fn extract(msg: Message) -> Option<Self::Response> {
         match msg {
             Message::Pong(response) => Some(response),
             _ => None,
         }
-        .expect("Msg was not the extpected response type")
     }
 }
@@ -8,8 +8,9 @@ use crate::prelude::{ripemd160, sha256, Hash};
 use openssl::{dsa::Dsa, pkey::*, sign::Verifier};
 use rand::{thread_rng, Rng};
 use std::{
-    io,
-    net::{SocketAddr, ToSocketAddrs},
+    fmt, io,
+    net::{Ipv6Addr, SocketAddr, ToSocketAddrs},
+    time::{Duration, SystemTime, UNIX_EPOCH},
     vec,
 };
 
@@ -17,10 +18,33 @@ pub fn gen_nonce() -> i32 {
     thread_rng().gen()
 }
 
+impl NodeAddress {
+    /// The host without the brackets that may surround an IPv6 literal
+    pub fn host(&self) -> &str {
+        self.host_name.trim_start_matches('[').trim_end_matches(']')
+    }
+}
+impl Peer {
+    /// None if the reported date can't be a point in time
+    pub fn reported_alive_at(&self) -> Option<SystemTime> {
+        if self.date < 0 {
+            return None;
+        }
+        UNIX_EPOCH.checked_add(Duration::from_millis(self.date as u64))
+    }
+}
 impl ToSocketAddrs for NodeAddress {
     type Iter = vec::IntoIter<SocketAddr>;
     fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
-        (&*self.host_name, self.port as u16).to_socket_addrs()
+        (self.host(), self.port as u16).to_socket_addrs()
+    }
+}
+impl fmt::Display for NodeAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.host().parse::<Ipv6Addr>() {
+            Ok(ip) => write!(f, "[{}]:{}", ip, self.port),
+            Err(_) => write!(f, "{}:{}", self.host_name, self.port),
+        }
     }
 }
 
@@ -67,6 +91,24 @@ impl StoragePayload {
         .into()
     }
 }
+impl StorageEntryWrapper {
+    pub fn protected_storage_entry(&self) -> Option<&ProtectedStorageEntry> {
+        match self.message.as_ref()? {
+            storage_entry_wrapper::Message::ProtectedStorageEntry(entry) => Some(entry),
+            storage_entry_wrapper::Message::ProtectedMailboxStorageEntry(mailbox_entry) => {
+                mailbox_entry.entry.as_ref()
+            }
+        }
+    }
+    pub fn bisq_hash(&self) -> Option<SequencedMessageHash> {
+        Some(
+            self.protected_storage_entry()?
+                .storage_payload
+                .as_ref()?
+                .bisq_hash(),
+        )
+    }
+}
 impl ProtectedStorageEntry {
     fn owner_pub_key(&self) -> Option<PKey<Public>> {
         PKey::from_dsa(Dsa::public_key_from_der(&self.owner_pub_key_bytes).ok()?).ok()
@@ -77,6 +119,17 @@ impl ProtectedStorageEntry {
             warn!("Invalid public key in ProtectedStorageEntry");
             return None;
         }
+        self.verify_signature()
+    }
+    pub fn verify_removal(&self, owner_pub_key: &[u8]) -> Option<SequencedMessageHash> {
+        if &*self.owner_pub_key_bytes != owner_pub_key {
+            warn!("Public key of removal doesn't match owner of ProtectedStorageEntry");
+            return None;
+        }
+        self.verify_signature()
+    }
+    fn verify_signature(&self) -> Option<SequencedMessageHash> {
+        let payload = self.storage_payload.as_ref()?;
         let pub_key = self.owner_pub_key()?;
         let verifier = Verifier::new_without_digest(&pub_key).ok()?;
         let hash = DataAndSeqNrPair {
@@ -100,12 +153,25 @@ impl ProtectedStorageEntry {
             })
     }
 }
+impl ProtectedMailboxStorageEntry {
+    pub fn verify_removal(&self) -> Option<SequencedMessageHash> {
+        let entry = self.entry.as_ref()?;
+        match entry.storage_payload.as_ref()?.message.as_ref()? {
+            storage_payload::Message::MailboxStoragePayload(payload)
+                if payload.owner_pub_key_bytes == self.receivers_pub_key_bytes => {}
+            _ => {
+                warn!("Removal of ProtectedMailboxStorageEntry not requested by receiver");
+                return None;
+            }
+        }
+        entry.verify_removal(&self.receivers_pub_key_bytes)
+    }
+}
 impl RefreshOfferMessage {
-    pub fn payload_hash(&self) -> SequencedMessageHash {
-        SequencedMessageHash::new(
-            sha256::Hash::from_slice(&self.hash_of_payload)
-                .expect("Couldn't unwrap RefreshOfferMessage.hash_of_data"),
-        )
+    pub fn payload_hash(&self) -> Option<SequencedMessageHash> {
+        sha256::Hash::from_slice(&self.hash_of_payload)
+            .ok()
+            .map(SequencedMessageHash::new)
     }
     pub fn verify(&self, owner_pub_key: &[u8], original_payload: &StoragePayload) -> Option<()> {
         let hash = DataAndSeqNrPair {
@@ -137,27 +203,19 @@ impl RefreshOfferMessage {
 }
 
 impl PersistableNetworkPayload {
-    pub fn bisq_hash(&self) -> PersistentMessageHash {
-        let inner = match self
-            .message
-            .as_ref()
-            .expect("PersistableNetworkPayload doesn't have message attached")
-        {
+    pub fn bisq_hash(&self) -> Option<PersistentMessageHash> {
+        let inner = match self.message.as_ref()? {
             persistable_network_payload::Message::AccountAgeWitness(witness) => {
-                ripemd160::Hash::from_slice(&witness.hash)
-                    .expect("AccountAgeWitness.hash is not correct")
+                ripemd160::Hash::from_slice(&witness.hash).ok()?
             }
             persistable_network_payload::Message::TradeStatistics2(stats) => {
-                ripemd160::Hash::from_slice(&stats.hash)
-                    .expect("TradeStatistics2.hash is not correct")
+                ripemd160::Hash::from_slice(&stats.hash).ok()?
             }
             persistable_network_payload::Message::ProposalPayload(prop) => {
-                ripemd160::Hash::from_slice(&prop.hash)
-                    .expect("ProposalPayload.hash is not correct")
+                ripemd160::Hash::from_slice(&prop.hash).ok()?
             }
             persistable_network_payload::Message::BlindVotePayload(vote) => {
-                ripemd160::Hash::from_slice(&vote.hash)
-                    .expect("BlindVotePayload.hash is not correct")
+                ripemd160::Hash::from_slice(&vote.hash).ok()?
             }
             persistable_network_payload::Message::SignedWitness(witness) => {
                 let mut data = witness.witness_hash.clone();
@@ -167,7 +225,7 @@ impl PersistableNetworkPayload {
                 ripemd160::Hash::hash(&hash.into_inner())
             }
         };
-        PersistentMessageHash::new(inner)
+        Some(PersistentMessageHash::new(inner))
     }
 }
 
@@ -182,6 +240,21 @@ macro_rules! into_message {
 }
 for_all_payloads!(into_message);
 
+impl network_envelope::Message {
+    pub fn name(&self) -> &'static str {
+        let msg = self;
+        macro_rules! name_of {
+            ($caml:ident, $snake:ident) => {
+                if let network_envelope::Message::$caml(_) = msg {
+                    return stringify!($caml);
+                }
+            };
+        }
+        for_all_payloads!(name_of);
+        unreachable!("Message missing from for_all_payloads")
+    }
+}
+
 pub enum Extract<P> {
     Succeeded(P),
     Failed(network_envelope::Message),
@@ -0,0 +1,26 @@
+use crate::error;
+use prost::Message;
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+};
+
+pub fn load<M: Message + Default>(path: &Path) -> Option<M> {
+    let mut serialized = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut serialized))
+        .ok()?;
+    M::decode(&*serialized)
+        .map_err(|e| warn!("Couldn't decode {:?}: {:?}", path, e))
+        .ok()
+}
+
+pub fn store<M: Message>(path: &Path, msg: &M) -> Result<(), error::Error> {
+    let mut serialized = Vec::with_capacity(msg.encoded_len());
+    msg.encode(&mut serialized)?;
+    let tmp_path = path.with_extension("tmp");
+    File::create(&tmp_path)?.write_all(&serialized)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
@@ -1,9 +1,12 @@
 use crate::{
     bisq::{constants::BaseCurrencyNetwork, payload::*},
-    p2p::{dispatch::*, Connection, ConnectionId, Request},
+    error::Error,
+    p2p::{
+        dispatch::*, transport::Socks, Connection, ConnectionId, Request, DEFAULT_MAX_FRAME_SIZE,
+    },
     prelude::*,
 };
-use std::{process, time::SystemTime};
+use std::{net::SocketAddr, process, time::SystemTime};
 
 #[derive(Debug, Clone, Copy)]
 struct DummyDispatcher;
@@ -13,14 +16,15 @@ impl Dispatcher for DummyDispatcher {
     }
 }
 
-pub fn check_node(network: BaseCurrencyNetwork, addr: NodeAddress, proxy_port: u16) {
+pub fn check_node(network: BaseCurrencyNetwork, addr: NodeAddress, proxy: SocketAddr) {
     let _ = System::run(move || {
         Arbiter::spawn(
             Connection::open(
                 addr.clone(),
                 network.into(),
                 DummyDispatcher,
-                Some(proxy_port),
+                &Socks { proxy },
+                DEFAULT_MAX_FRAME_SIZE,
             )
             .map_err(|_| {
                 eprintln!("CRITICAL - Unable to connect to node");
@@ -49,6 +53,10 @@ pub fn check_node(network: BaseCurrencyNetwork, addr: NodeAddress, proxy_port: u
                             );
                             process::exit(0)
                         }
+                        Err(Error::RequestTimeout) => {
+                            eprintln!("CRITICAL - Timed out waiting for response from host");
+                            process::exit(2)
+                        }
                         Err(_) => {
                             eprintln!("CRITICAL - No response from host");
                             process::exit(2)
@@ -2,17 +2,25 @@ mod query;
 
 use crate::{
     api::Client,
-    bisq::constants::*,
+    bisq::{constants::*, payload::GetDataResponse, persistence},
     daemon::{self, DaemonConfig},
     domain::{currency::Currency, market::Market},
-    p2p::TorConfig,
+    fixture,
+    p2p::{BundleConfig, OnionVersion, TorConfig},
 };
 use clap::{clap_app, crate_version, App, ArgMatches};
 use env_logger::Env;
 use log::Level;
 use query::*;
 use reqwest;
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    process,
+    str::FromStr,
+    time::Duration,
+};
 
 fn app() -> App<'static, 'static> {
     let app = clap_app!(risq =>
@@ -22,15 +30,38 @@ fn app() -> App<'static, 'static> {
         (@subcommand daemon =>
          (about: "Runs the risq p2p node")
          (visible_alias: "d")
+         (@arg ADVERTISED_ADDRESS: --("advertised-address") +takes_value "Host name or IP other peers should use to reach this node when tor is off")
          (@arg API_PORT: --("api-port") default_value("7477") {port} "API port")
+         (@arg BAN_PERIOD: --("ban-period") default_value("24") {number} "Hours a misbehaving peer stays banned")
+         (@arg BOOTSTRAP_SEEDS: --("bootstrap-seeds") default_value("1") {number} "Number of seed nodes to bootstrap from in parallel")
+         (@arg BUNDLE_WINDOW: --("bundle-window") default_value("50") {number} "Milliseconds to wait for more messages once outgoing messages queue up")
+         (@arg CAPTURE: --capture +takes_value "Append every received message to this file, see `risq replay`")
+         (@arg LISTEN_ADDRESS: --("listen-address") default_value("127.0.0.1") {ip} "IP address (v4 or v6) the p2p node listens on")
          (@arg LOG_LEVEL: -l --("log-level") default_value("info") {level} "(error|warn|info|debug|trace)")
+         (@arg MAX_FAILED_PINGS: --("max-failed-pings") default_value("3") {number} "Consecutive unanswered pings after which a peer is disconnected")
+         (@arg MAX_FRAME_SIZE: --("max-frame-size") default_value("10485760") {number} "Maximum size in bytes of a message frame before the connection is closed")
+         (@arg MAX_PEER_AGE: --("max-peer-age") default_value("72") {number} "Hours after which an unseen peer is forgotten")
+         (@arg MAX_BUNDLE_SIZE: --("max-bundle-size") default_value("100") {number} "Maximum number of messages sent in one BundleOfEnvelopes")
+         (@arg ONION_KEY: --("onion-key") +takes_value "File holding the private key of the hidden service")
+         (@arg ONION_VERSION: --("onion-version") default_value("3") {onion_version} "(2|3) Version of the hidden service to create")
          (@arg NETWORK: -n --network default_value("BtcMainnet") {network} "(BtcRegtest|BtcTestnet|BtcMainnet)")
          (@arg P2P_PORT: -p --("p2p-port") default_value("5000") {port} "Port of p2p node")
+         (@arg RESYNC_INTERVAL: --("resync-interval") default_value("10") {positive_number} "Minutes between asking a random peer for data this node is missing")
+         (@arg SEED_NODE: --("seed-node") "Run as a seed node")
          (@arg TOR_ACTIVE: --("tor-active") default_value("true") {boolean} "Run daemon behind tor")
+         (@arg TOR_CONTROL_HOST: --("tor-control-host") default_value("localhost") "Host of the tor ControlPort")
+         (@arg TOR_CONTROL_PASSWORD: --("tor-control-password") +takes_value "Password for tor's HashedControlPassword authentication")
          (@arg TOR_CONTROL_PORT: --("tor-controll-port") default_value("9051") {port} "Tor Control port")
          (@arg TOR_HIDDEN_SERVICE_PORT: --("tor-hidden-service-port") default_value("9999") {port} "Public port of the hidden service")
+         (@arg TOR_ONION_TARGET: --("tor-onion-target") +takes_value "host:port tor forwards hidden service connections to, defaults to the p2p port on tor's host")
+         (@arg TOR_SOCKS_HOST: --("tor-socks-host") default_value("127.0.0.1") "Host of the tor SOCKSPort")
          (@arg TOR_SOCKS_PORT: --("tor-socks-port") default_value("9050") {port} "Tor SOCKSPort")
         )
+        (@subcommand replay =>
+         (about: "Feeds a capture into a fresh offer book without networking and prints the outcome")
+         (@arg LOG_LEVEL: -l --("log-level") default_value("warn") {level} "(error|warn|info|debug|trace)")
+         (@arg FILE: +required "Capture written by `risq daemon --capture`")
+        )
         (@subcommand offers =>
          (about: "Subcomand to interact with offers")
          (@arg API_PORT: --("api-port") default_value("7477") {port} "API port")
@@ -38,7 +69,7 @@ fn app() -> App<'static, 'static> {
         )
     );
 
-    add_checker_cmd(app)
+    add_checker_cmd(add_fixture_cmd(app))
 }
 
 pub fn run() -> () {
@@ -46,6 +77,8 @@ pub fn run() -> () {
     match matches.subcommand() {
         ("daemon", Some(matches)) => daemon(matches),
         ("offers", Some(matches)) => offers(matches),
+        ("replay", Some(matches)) => replay(matches),
+        ("fixture-seed", Some(matches)) => fixture_seed(matches),
         #[cfg(feature = "checker")]
         ("check-node", Some(matches)) => check_node(matches),
         _ => unreachable!(),
@@ -73,6 +106,31 @@ fn market(market: String) -> Result<(), String> {
     }
     Ok(())
 }
+fn number(n: String) -> Result<(), String> {
+    match u64::from_str(&n) {
+        Err(_) => Err(format!("'{}' is not a valid number", n).into()),
+        Ok(_) => Ok(()),
+    }
+}
+fn positive_number(n: String) -> Result<(), String> {
+    match u64::from_str(&n) {
+        Err(_) => Err(format!("'{}' is not a valid number", n).into()),
+        Ok(0) => Err(format!("'{}' must be greater than 0", n).into()),
+        Ok(_) => Ok(()),
+    }
+}
+fn ip(ip: String) -> Result<(), String> {
+    match IpAddr::from_str(&ip) {
+        Err(_) => Err(format!("'{}' is not a valid ip address", ip).into()),
+        Ok(_) => Ok(()),
+    }
+}
+fn onion_version(version: String) -> Result<(), String> {
+    match OnionVersion::from_str(&version) {
+        Err(_) => Err("(2|3)".into()),
+        Ok(_) => Ok(()),
+    }
+}
 fn boolean(b: String) -> Result<(), String> {
     match bool::from_str(&b) {
         Err(_) => Err(format!("'{}' is not a valid boolean", b).into()),
@@ -87,31 +145,92 @@ fn level(level: String) -> Result<(), String> {
 }
 
 fn daemon(matches: &ArgMatches) {
-    let mut private_key_path = dirs::home_dir().expect("Couldn't determin home dir");
-    private_key_path.push(".risq/tor/service.key");
+    let mut risq_home = dirs::home_dir().expect("Couldn't determin home dir");
+    risq_home.push(".risq");
+    let onion_version: OnionVersion = matches.value_of("ONION_VERSION").unwrap().parse().unwrap();
+    let legacy_key_path = risq_home.join("tor/service.key");
+    let private_key_path = matches
+        .value_of("ONION_KEY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| match onion_version {
+            OnionVersion::V2 => legacy_key_path.clone(),
+            OnionVersion::V3 => risq_home.join("tor/service_v3.key"),
+        });
+    let seed_node = matches.is_present("SEED_NODE");
 
     let network: BaseCurrencyNetwork = matches.value_of("NETWORK").unwrap().parse().unwrap();
     let api_port = matches.value_of("API_PORT").unwrap().parse().unwrap();
     let server_port = matches.value_of("P2P_PORT").unwrap().parse().unwrap();
+    let listen_addr = matches.value_of("LISTEN_ADDRESS").unwrap().parse().unwrap();
+    let advertised_addr = matches.value_of("ADVERTISED_ADDRESS").map(|addr| {
+        addr.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string()
+    });
     let tor_active: bool = matches.value_of("TOR_ACTIVE").unwrap().parse().unwrap();
     let level: String = matches.value_of("LOG_LEVEL").unwrap().parse().unwrap();
+    let bootstrap_seeds = matches
+        .value_of("BOOTSTRAP_SEEDS")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let ban_period_hours: u64 = matches.value_of("BAN_PERIOD").unwrap().parse().unwrap();
+    let max_peer_age_hours: u64 = matches.value_of("MAX_PEER_AGE").unwrap().parse().unwrap();
+    let bundle_window_millis: u64 = matches.value_of("BUNDLE_WINDOW").unwrap().parse().unwrap();
+    let resync_interval_mins: u64 = matches
+        .value_of("RESYNC_INTERVAL")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let max_frame_size = matches.value_of("MAX_FRAME_SIZE").unwrap().parse().unwrap();
+    let max_failed_pings = matches
+        .value_of("MAX_FAILED_PINGS")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let max_bundle_size = matches
+        .value_of("MAX_BUNDLE_SIZE")
+        .unwrap()
+        .parse()
+        .unwrap();
     let env = Env::default().filter_or("RUST_LOG", level);
     env_logger::init_from_env(env);
-    let (tor_proxy_port, tor_config) = if tor_active {
+    if tor_active
+        && onion_version == OnionVersion::V3
+        && !private_key_path.exists()
+        && legacy_key_path.exists()
+    {
+        info!(
+            "Found a v2 onion key at {:?}, run with --onion-version 2 to keep its address",
+            legacy_key_path
+        );
+    }
+    if seed_node && tor_active && !private_key_path.exists() {
+        eprintln!(
+            "A seed node needs its onion key, none found at {:?}",
+            private_key_path
+        );
+        process::exit(1);
+    }
+    let (tor_proxy, tor_config) = if tor_active {
         (
-            Some(matches.value_of("TOR_SOCKS_PORT").unwrap().parse().unwrap()),
+            Some(socks_proxy(matches)),
             Some(TorConfig {
                 hidden_service_port: matches
                     .value_of("TOR_HIDDEN_SERVICE_PORT")
                     .unwrap()
                     .parse()
                     .unwrap(),
+                tc_host: matches.value_of("TOR_CONTROL_HOST").unwrap().to_string(),
                 tc_port: matches
                     .value_of("TOR_CONTROL_PORT")
                     .unwrap()
                     .parse()
                     .unwrap(),
+                onion_target: matches.value_of("TOR_ONION_TARGET").map(String::from),
                 private_key_path,
+                onion_version,
+                control_password: matches.value_of("TOR_CONTROL_PASSWORD").map(String::from),
             }),
         )
     } else {
@@ -120,12 +239,40 @@ fn daemon(matches: &ArgMatches) {
     daemon::run(DaemonConfig {
         api_port,
         server_port,
+        listen_addr,
+        advertised_addr,
         network,
         tor_config,
-        tor_proxy_port,
+        tor_proxy,
+        risq_home,
+        max_peer_age: Duration::from_secs(max_peer_age_hours * 60 * 60),
+        bootstrap_seeds,
+        ban_period: Duration::from_secs(ban_period_hours * 60 * 60),
+        max_failed_pings,
+        bundling: BundleConfig {
+            window: Duration::from_millis(bundle_window_millis),
+            max_size: max_bundle_size,
+        },
+        max_frame_size,
+        capture_file: matches.value_of("CAPTURE").map(PathBuf::from),
+        seed_node,
+        resync_interval: Duration::from_secs(resync_interval_mins * 60),
     });
 }
 
+// The proxy host may be a name, e.g. of a tor container, so it is resolved once at startup
+fn socks_proxy(matches: &ArgMatches) -> SocketAddr {
+    let host = matches.value_of("TOR_SOCKS_HOST").unwrap();
+    let port: u16 = matches.value_of("TOR_SOCKS_PORT").unwrap().parse().unwrap();
+    match (host, port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        _ => {
+            eprintln!("Couldn't resolve tor SOCKS proxy {}:{}", host, port);
+            process::exit(1);
+        }
+    }
+}
+
 fn offers(matches: &ArgMatches) {
     let api_port = matches.value_of("API_PORT").unwrap().parse().unwrap();
     let mut args = HashMap::new();
@@ -149,6 +296,56 @@ fn offers(matches: &ArgMatches) {
         Err(_) => println!("Error trying to reach api"),
     }
 }
+fn replay(matches: &ArgMatches) {
+    let level: String = matches.value_of("LOG_LEVEL").unwrap().parse().unwrap();
+    env_logger::init_from_env(Env::default().filter_or("RUST_LOG", level));
+    daemon::replay(&PathBuf::from(matches.value_of("FILE").unwrap()));
+}
+
+fn add_fixture_cmd(app: App<'static, 'static>) -> App<'static, 'static> {
+    use clap::{Arg, SubCommand};
+    app.subcommand(
+        SubCommand::with_name("fixture-seed")
+            .about("Serves a recorded GetDataResponse on the regtest seed node ports. Used for development.")
+            .arg(
+                Arg::with_name("LISTEN_ADDRESS")
+                    .long("listen-address")
+                    .validator(ip)
+                    .default_value("127.0.0.1"),
+            )
+            .arg(
+                Arg::with_name("LOG_LEVEL")
+                    .long("log-level")
+                    .short("l")
+                    .validator(level)
+                    .default_value("info"),
+            )
+            .arg(Arg::with_name("FILE").index(1).required(true))
+            .after_help("Run the daemon with `-n BtcRegtest --tor-active=false` to bootstrap from it."),
+    )
+}
+
+fn fixture_seed(matches: &ArgMatches) {
+    let level: String = matches.value_of("LOG_LEVEL").unwrap().parse().unwrap();
+    env_logger::init_from_env(Env::default().filter_or("RUST_LOG", level));
+    let path = PathBuf::from(matches.value_of("FILE").unwrap());
+    let listen_addr = matches.value_of("LISTEN_ADDRESS").unwrap().parse().unwrap();
+    let data: GetDataResponse = match persistence::load(&path) {
+        Some(data) => data,
+        None => {
+            eprintln!("Couldn't load a GetDataResponse from {:?}", path);
+            process::exit(1);
+        }
+    };
+    info!(
+        "Loaded {} entries and {} payloads from {:?}",
+        data.data_set.len(),
+        data.persistable_network_payload_items.len(),
+        path
+    );
+    fixture::serve(data, listen_addr);
+}
+
 #[cfg(not(feature = "checker"))]
 fn add_checker_cmd(app: App<'static, 'static>) -> App<'static, 'static> {
     app
@@ -159,6 +356,11 @@ fn add_checker_cmd(app: App<'static, 'static>) -> App<'static, 'static> {
     app.subcommand(
         SubCommand::with_name("check-node")
             .about("Send a ping to a node. Used for monitoring.")
+            .arg(
+                Arg::with_name("TOR_SOCKS_HOST")
+                    .long("tor-socks-host")
+                    .default_value("127.0.0.1"),
+            )
             .arg(
                 Arg::with_name("TOR_SOCKS_PORT")
                     .long("tor-socks-port")
@@ -188,9 +390,12 @@ fn check_node(matches: &ArgMatches) {
     use crate::bisq::NodeAddress;
     use crate::checker;
 
-    let socks_port = matches.value_of("TOR_SOCKS_PORT").unwrap().parse().unwrap();
     let host_name: String = matches.value_of("NODE_HOST").unwrap().into();
     let port = matches.value_of("NODE_PORT").unwrap().parse().unwrap();
     let network: BaseCurrencyNetwork = matches.value_of("NETWORK").unwrap().parse().unwrap();
-    checker::check_node(network, NodeAddress { host_name, port }, socks_port);
+    checker::check_node(
+        network,
+        NodeAddress { host_name, port },
+        socks_proxy(matches),
+    );
 }
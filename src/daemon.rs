@@ -1,64 +1,209 @@
 mod convert;
 mod data_router;
+mod data_store;
+mod replay;
+mod shutdown;
+#[cfg(test)]
+mod tests;
 
 use crate::{
     api,
-    bisq::constants::BaseCurrencyNetwork,
+    bisq::constants::{local_capabilities, BaseCurrencyNetwork},
     domain::{offer::*, price_feed::PriceFeed, statistics::StatsCache},
-    p2p::{dispatch::ActorDispatcher, server, Bootstrap, Broadcaster, Peers, TorConfig},
+    p2p::{
+        dispatch::{
+            capture::{Capture, Captured},
+            ActorDispatcher,
+        },
+        message::{GetBannedPeers, GetNetworkInfo, ReportMisbehaviourTo},
+        server, transport, Bootstrap, Broadcaster, BundleConfig, Peers, PeersConfig, TorConfig,
+    },
     prelude::*,
 };
+#[cfg(feature = "fuzz")]
+pub(crate) use convert::open_offer;
+#[cfg(all(feature = "fuzz", feature = "statistics"))]
+pub(crate) use convert::trade_statistics2;
 use data_router::*;
-use std::fs;
+pub use data_router::{GetResyncStats, ResyncStats};
+pub use replay::replay;
+use shutdown::Shutdown;
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    process,
+    sync::mpsc,
+    time::Duration,
+};
 
 pub struct DaemonConfig {
     pub api_port: u16,
     pub server_port: u16,
+    pub listen_addr: IpAddr,
+    pub advertised_addr: Option<String>,
     pub network: BaseCurrencyNetwork,
     pub tor_config: Option<TorConfig>,
-    pub tor_proxy_port: Option<u16>,
+    pub tor_proxy: Option<SocketAddr>,
+    pub risq_home: PathBuf,
+    pub max_peer_age: Duration,
+    pub bootstrap_seeds: usize,
+    pub ban_period: Duration,
+    pub max_failed_pings: u32,
+    pub bundling: BundleConfig,
+    pub max_frame_size: usize,
+    pub capture_file: Option<PathBuf>,
+    pub seed_node: bool,
+    pub resync_interval: Duration,
 }
-pub fn run(
-    DaemonConfig {
+pub fn run(config: DaemonConfig) {
+    let sys = System::new("risq");
+    let api_port = config.api_port;
+    let price_feed = PriceFeed::start(config.tor_proxy);
+    let node = start(config, price_feed);
+
+    // Api Thread
+    let _ = api::listen(
         api_port,
+        node.offer_book,
+        node.stats_cache,
+        node.banned_peers,
+        node.network_info,
+        node.resync_stats,
+    );
+
+    if let Err(e) = sys.run() {
+        error!("Daemon stopped: {}", e);
+        process::exit(1);
+    }
+}
+
+// What the api (or a test) needs to observe a running node
+struct Node {
+    offer_book: Addr<OfferBook>,
+    stats_cache: Option<StatsCache>,
+    banned_peers: Recipient<GetBannedPeers>,
+    network_info: Recipient<GetNetworkInfo>,
+    resync_stats: Recipient<GetResyncStats>,
+}
+
+// Starts the actors of a node on the current System
+fn start(
+    DaemonConfig {
+        api_port: _,
         server_port,
+        listen_addr,
+        advertised_addr,
         network,
         tor_config,
-        tor_proxy_port,
+        tor_proxy,
+        risq_home,
+        max_peer_age,
+        bootstrap_seeds,
+        ban_period,
+        max_failed_pings,
+        bundling,
+        max_frame_size,
+        capture_file,
+        seed_node,
+        resync_interval,
     }: DaemonConfig,
-) {
+    price_feed: Addr<PriceFeed>,
+) -> Node {
     if let Some(tor_config) = tor_config.as_ref() {
         fs::create_dir_all(tor_config.private_key_path.parent().unwrap())
             .expect("Couldn't create risq dir");
     }
-
-    let sys = System::new("risq");
+    let mut network_dir = risq_home;
+    network_dir.push(format!("{:?}", network));
+    fs::create_dir_all(&network_dir).expect("Couldn't create network dir");
+    let capture =
+        capture_file.map(|path| Capture::open(&path, network).expect("Couldn't open capture file"));
+    let peers_config = PeersConfig {
+        peers_file: network_dir.join("peers"),
+        max_peer_age,
+        bans_file: network_dir.join("bans"),
+        ban_period,
+        max_failed_pings,
+        bundling,
+        max_frame_size,
+        seed_node,
+    };
 
     // Domain Thread
-    let price_feed = PriceFeed::start(tor_proxy_port);
+    let transport = transport::from_proxy(tor_proxy);
     let offer_book = OfferBook::start(price_feed);
     let stats_cache = StatsCache::new();
 
     let offer_book_clone = offer_book.clone();
     let stats_cache_clone = stats_cache.as_ref().map(Clone::clone);
+    let (peers_send, peers_rec) = mpsc::channel();
 
     Arbiter::new().exec_fn(move || {
         // Daemon Thread
         let broadcaster = Broadcaster::start();
-        let data_router =
-            DataRouter::start(offer_book_clone, broadcaster.clone(), stats_cache_clone);
-        let dispatcher = ActorDispatcher::<DataRouter, DataRouterDispatch>::new(data_router);
+        let data_router = DataRouter::start(
+            offer_book_clone,
+            broadcaster.clone(),
+            stats_cache_clone,
+            local_capabilities(seed_node),
+            Some(resync_interval),
+        );
+        let dispatcher = Captured::new(
+            ActorDispatcher::<DataRouter, DataRouterDispatch>::new(data_router.clone()),
+            capture,
+        );
 
         Arbiter::new().exec_fn(move || {
             // P2P Thread
-            let peers = Peers::start(network, broadcaster, dispatcher.clone(), tor_proxy_port);
-            let bootstrap = Bootstrap::start(network, peers.clone(), dispatcher, tor_proxy_port);
-            server::start(server_port, peers, bootstrap, tor_config);
+            let peers = Peers::start(
+                network,
+                broadcaster.clone(),
+                dispatcher.clone(),
+                transport.clone(),
+                peers_config,
+            );
+            data_router.do_send(ReportMisbehaviourTo(peers.clone().recipient()));
+            data_router.do_send(ResyncVia(peers.clone().recipient()));
+            peers_send
+                .send((
+                    peers.clone().recipient(),
+                    peers.clone().recipient(),
+                    data_router.clone().recipient(),
+                ))
+                .expect("Couldn't hand Peers to the api");
+            let bootstrap = Bootstrap::start(
+                network,
+                peers.clone(),
+                dispatcher,
+                transport,
+                max_frame_size,
+                bootstrap_seeds,
+                seed_node,
+            );
+            let server = server::start(
+                listen_addr,
+                server_port,
+                advertised_addr,
+                peers.clone(),
+                bootstrap,
+                tor_config,
+            );
+            Shutdown::start(
+                broadcaster.recipient(),
+                peers.recipient(),
+                server.recipient(),
+            );
         });
     });
 
-    // Api Thread
-    let _ = api::listen(api_port, offer_book, stats_cache);
-
-    let _ = sys.run();
+    let (banned_peers, network_info, resync_stats) =
+        peers_rec.recv().expect("P2P thread didn't start");
+    Node {
+        offer_book,
+        stats_cache,
+        banned_peers,
+        network_info,
+        resync_stats,
+    }
 }
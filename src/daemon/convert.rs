@@ -9,7 +9,6 @@ use crate::{
         market::Market,
         offer::{message::*, *},
     },
-    prelude::{sha256, Hash},
 };
 use std::{
     convert::TryFrom,
@@ -27,19 +26,16 @@ impl TryFrom<offer_payload::Direction> for OfferDirection {
     }
 }
 
-pub fn refresh_offer(msg: &RefreshOfferMessage) -> RefreshOffer {
+pub fn refresh_offer(msg: &RefreshOfferMessage, bisq_hash: SequencedMessageHash) -> RefreshOffer {
     RefreshOffer {
         sequence: msg.sequence_number.into(),
-        bisq_hash: SequencedMessageHash::new(
-            sha256::Hash::from_slice(&msg.hash_of_payload)
-                .expect("Couldn't unwrap RefreshOfferMessage.hash_of_data"),
-        ),
+        bisq_hash,
     }
 }
 
 pub fn open_offer(entry: ProtectedStorageEntry, hash: SequencedMessageHash) -> Option<OpenOffer> {
-    let created_at =
-        SystemTime::UNIX_EPOCH + Duration::from_millis(entry.creation_time_stamp as u64);
+    let created_at = SystemTime::UNIX_EPOCH
+        .checked_add(Duration::from_millis(entry.creation_time_stamp as u64))?;
     let storage_payload = entry.storage_payload?;
     if let storage_payload::Message::OfferPayload(payload) = storage_payload.message? {
         let direction = offer_payload::Direction::from_i32(payload.direction)
@@ -116,7 +112,7 @@ mod statistics {
     };
 
     pub fn trade_statistics2(payload: PersistableNetworkPayload) -> Option<statistics::Trade> {
-        let hash = payload.bisq_hash();
+        let hash = payload.bisq_hash()?;
         if let persistable_network_payload::Message::TradeStatistics2(payload) = payload.message? {
             if payload.trade_price <= 0 || payload.trade_amount <= 0 {
                 return None;
@@ -141,7 +137,7 @@ mod statistics {
                     base.bisq_internal_precision(),
                 ),
                 payload.payment_method_id,
-                UNIX_EPOCH + Duration::from_millis(payload.trade_date as u64),
+                UNIX_EPOCH.checked_add(Duration::from_millis(payload.trade_date as u64))?,
                 hash,
             ))
         } else {
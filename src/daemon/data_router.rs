@@ -1,33 +1,54 @@
-use super::convert;
+use super::{convert, data_store::DataStore};
 use crate::{
     bisq::{
         payload::{kind::*, *},
-        PersistentMessageHash, SequencedMessageHash,
+        SequencedMessageHash,
     },
     domain::{
         offer::{message::*, OfferBook},
         statistics::{StatsCache, Trade},
         CommandResult,
     },
-    p2p::{dispatch::Receive, message::Broadcast, Broadcaster, ConnectionId},
+    p2p::{
+        dispatch::Receive,
+        message::{
+            Broadcast, Misbehaviour, Offence, ReportMisbehaviourTo, RequestUpdatedData, SendTo,
+        },
+        Broadcaster, ConnectionId,
+    },
     prelude::*,
 };
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     mem,
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// A peer sends its GetUpdatedDataRequest right after the preliminary response
+const PEER_CAPABILITIES_TTL: Duration = Duration::from_secs(5 * 60);
+
 pub struct DataRouter {
     offer_book: Addr<OfferBook>,
     broadcaster: Addr<Broadcaster>,
     #[cfg(feature = "statistics")]
     stats_cache: StatsCache,
     sequenced_message_info: HashMap<SequencedMessageHash, SequencedMessageInfo>,
-    persistent_message_info: HashSet<PersistentMessageHash>,
+    store: DataStore,
+    peer_capabilities: HashMap<ConnectionId, (Vec<i32>, Instant)>,
+    misbehaviour: Option<Recipient<Misbehaviour>>,
+    resync: Option<Recipient<RequestUpdatedData>>,
+    resync_interval: Option<Duration>,
+    resync_stats: ResyncStats,
 }
 impl Actor for DataRouter {
     type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(interval) = self.resync_interval {
+            ctx.run_interval(interval, |data_router, ctx| data_router.resync(ctx));
+        }
+        ctx.run_interval(PRUNE_INTERVAL, |data_router, _| data_router.prune());
+    }
 }
 struct SequencedMessageInfo {
     last_delivery: SystemTime,
@@ -44,6 +65,8 @@ impl DataRouter {
         offer_book: Addr<OfferBook>,
         broadcaster: Addr<Broadcaster>,
         stats_cache: Option<StatsCache>,
+        capabilities: Vec<i32>,
+        resync_interval: Option<Duration>,
     ) -> Addr<DataRouter> {
         DataRouter {
             offer_book,
@@ -51,7 +74,12 @@ impl DataRouter {
             #[cfg(feature = "statistics")]
             stats_cache: stats_cache.expect("StatsCache missing"),
             sequenced_message_info: HashMap::new(),
-            persistent_message_info: HashSet::new(),
+            store: DataStore::new(capabilities),
+            peer_capabilities: HashMap::new(),
+            misbehaviour: None,
+            resync: None,
+            resync_interval,
+            resync_stats: ResyncStats::default(),
         }
         .start()
     }
@@ -71,28 +99,94 @@ impl DataRouter {
         }
     }
 
+    fn report(&self, origin: ConnectionId, offence: Offence) {
+        if let Some(recipient) = self.misbehaviour.as_ref() {
+            let _ = recipient.do_send(Misbehaviour(origin, offence));
+        }
+    }
+
+    fn send_data_response(&self, origin: ConnectionId, response: GetDataResponse) {
+        debug!(
+            "Serving {} entries and {} payloads to {:?}",
+            response.data_set.len(),
+            response.persistable_network_payload_items.len(),
+            origin
+        );
+        arbiter_spawn!(self.broadcaster.send(SendTo(origin, response)));
+    }
+
+    fn prune(&mut self) {
+        let expired = self.store.prune_expired();
+        if expired > 0 {
+            debug!("Pruned {} expired entries", expired);
+        }
+        self.peer_capabilities
+            .retain(|_, (_, received_at)| received_at.elapsed() < PEER_CAPABILITIES_TTL);
+    }
+
+    fn resync(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let resync = match self.resync.as_ref() {
+            Some(resync) => resync,
+            None => return,
+        };
+        ctx.spawn(
+            fut::wrap_future(resync.send(RequestUpdatedData(self.store.keys())).flatten()).then(
+                |result, data_router: &mut Self, _ctx| {
+                    match result {
+                        Ok((origin, response)) => {
+                            let (entries, payloads) = data_router.route_bootstrap_data(
+                                origin,
+                                response.data_set,
+                                response.persistable_network_payload_items,
+                            );
+                            info!(
+                                "Resync from {:?} recovered {} entries and {} payloads",
+                                origin, entries, payloads
+                            );
+                            let stats = &mut data_router.resync_stats;
+                            stats.rounds += 1;
+                            stats.recovered_entries += entries;
+                            stats.recovered_payloads += payloads;
+                            stats.last_resync = Some(SystemTime::now());
+                        }
+                        Err(e) => warn!("Resync failed: {:?}", e),
+                    }
+                    fut::ok(())
+                },
+            ),
+        );
+    }
+
     fn route_bootstrap_data(
         &mut self,
+        origin: ConnectionId,
         data: Vec<StorageEntryWrapper>,
         payloads: Vec<PersistableNetworkPayload>,
-    ) {
-        data.into_iter().for_each(|w| {
-            self.route_storage_entry_wrapper(Some(w), Self::ignore_command_result());
-        });
+    ) -> (usize, usize) {
+        let entries = data
+            .into_iter()
+            .filter_map(|w| {
+                self.route_storage_entry_wrapper(origin, Some(w), Self::ignore_command_result())
+            })
+            .count();
         let mut trades = if cfg!(feature = "statistics") {
             Some(Vec::new())
         } else {
             None
         };
-        payloads.into_iter().for_each(|p| {
-            self.route_persistable_network_payload(
-                Some(p),
-                trades.as_mut(),
-                Self::ignore_command_result(),
-            );
-        });
+        let payloads = payloads
+            .into_iter()
+            .filter_map(|p| {
+                self.route_persistable_network_payload(
+                    Some(p),
+                    trades.as_mut(),
+                    Self::ignore_command_result(),
+                )
+            })
+            .count();
         #[cfg(feature = "statistics")]
         arbiter_spawn!(self.stats_cache.bootstrap(trades.unwrap()));
+        (entries, payloads)
     }
     fn should_deliver_sequenced(
         &mut self,
@@ -124,26 +218,38 @@ impl DataRouter {
     }
     fn route_storage_entry_wrapper(
         &mut self,
+        origin: ConnectionId,
         entry_wrapper: Option<StorageEntryWrapper>,
         result_handler: impl ResultHandler + 'static,
     ) -> Option<()> {
-        match entry_wrapper?.message? {
+        let entry_wrapper = entry_wrapper?;
+        let bisq_hash = entry_wrapper.bisq_hash()?;
+        let to_store = entry_wrapper.clone();
+        match entry_wrapper.message? {
             storage_entry_wrapper::Message::ProtectedStorageEntry(entry) => {
-                self.route_protected_storage_entry(Some(entry), result_handler);
+                self.route_protected_storage_entry(origin, Some(entry), result_handler)
             }
             storage_entry_wrapper::Message::ProtectedMailboxStorageEntry(entry) => {
-                self.route_protected_storage_entry(entry.entry, result_handler);
+                self.route_protected_storage_entry(origin, entry.entry, result_handler)
             }
-        }
-        .into()
+        }?;
+        self.store.add_entry(bisq_hash, to_store);
+        Some(())
     }
     fn route_protected_storage_entry(
         &mut self,
+        origin: ConnectionId,
         entry: Option<ProtectedStorageEntry>,
         result_handler: impl ResultHandler + 'static,
     ) -> Option<()> {
         let mut entry = entry?;
-        let bisq_hash = entry.verify()?;
+        let bisq_hash = match entry.verify() {
+            Some(hash) => hash,
+            None => {
+                self.report(origin, Offence::InvalidSignature);
+                return None;
+            }
+        };
         if !self.should_deliver_sequenced(
             bisq_hash,
             entry.sequence_number,
@@ -168,6 +274,77 @@ impl DataRouter {
         }
         .into()
     }
+    fn route_remove_data(
+        &mut self,
+        origin: ConnectionId,
+        entry: Option<ProtectedStorageEntry>,
+        result_handler: impl ResultHandler + 'static,
+    ) -> Option<()> {
+        let entry = entry?;
+        let bisq_hash = entry.storage_payload.as_ref()?.bisq_hash();
+        // Checked before the signature so replayed removals are cheap to drop
+        if !self.is_newer_sequence(&bisq_hash, entry.sequence_number) {
+            return None;
+        }
+        let owner_pub_key = &self.sequenced_message_info.get(&bisq_hash)?.owner_pub_key;
+        if entry.verify_removal(owner_pub_key).is_none() {
+            self.report(origin, Offence::InvalidSignature);
+            return None;
+        }
+        self.remove_sequenced(entry, bisq_hash, result_handler)
+    }
+    fn route_remove_mailbox_data(
+        &mut self,
+        origin: ConnectionId,
+        entry: Option<ProtectedMailboxStorageEntry>,
+        result_handler: impl ResultHandler + 'static,
+    ) -> Option<()> {
+        let entry = entry?;
+        let bisq_hash = match entry.verify_removal() {
+            Some(hash) => hash,
+            None => {
+                self.report(origin, Offence::InvalidSignature);
+                return None;
+            }
+        };
+        let entry = entry.entry?;
+        if !self.is_newer_sequence(&bisq_hash, entry.sequence_number) {
+            return None;
+        }
+        self.remove_sequenced(entry, bisq_hash, result_handler)
+    }
+    fn is_newer_sequence(&self, bisq_hash: &SequencedMessageHash, sequence: i32) -> bool {
+        self.sequenced_message_info
+            .get(bisq_hash)
+            .map_or(false, |info| sequence > info.sequence)
+    }
+    // Callers have made sure the removal is newer than what was delivered
+    fn remove_sequenced(
+        &mut self,
+        entry: ProtectedStorageEntry,
+        bisq_hash: SequencedMessageHash,
+        result_handler: impl ResultHandler + 'static,
+    ) -> Option<()> {
+        let info = self.sequenced_message_info.get_mut(&bisq_hash)?;
+        // Keep the info around so that a replayed add with an old sequence is rejected
+        info.sequence = entry.sequence_number;
+        info.last_delivery = SystemTime::now();
+        self.store.remove_entry(&bisq_hash);
+        match (&entry).into() {
+            StoragePayloadKind::OfferPayload => arbiter_spawn!(self
+                .offer_book
+                .send(RemoveOffer {
+                    bisq_hash,
+                    sequence: entry.sequence_number.into(),
+                })
+                .then(result_handler)),
+            // Nothing else tracks these, so the removal is accepted once the store forgets the entry
+            _ => {
+                let _ = result_handler(Ok(CommandResult::Accepted));
+            }
+        }
+        .into()
+    }
     #[allow(unused_variables)]
     fn route_persistable_network_payload(
         &mut self,
@@ -176,10 +353,11 @@ impl DataRouter {
         result_handler: impl ResultHandler + 'static,
     ) -> Option<()> {
         let payload = payload?;
-        let bisq_hash = payload.bisq_hash();
-        if !self.persistent_message_info.insert(bisq_hash) {
+        let bisq_hash = payload.bisq_hash()?;
+        if self.store.contains_payload(&bisq_hash) {
             return None;
         }
+        self.store.add_payload(bisq_hash, payload.clone());
         match PersistableNetworkPayloadKind::from(&payload) {
             #[cfg(feature = "statistics")]
             PersistableNetworkPayloadKind::TradeStatistics2 => {
@@ -202,6 +380,10 @@ pub enum DataRouterDispatch {
     RefreshOffer(RefreshOfferMessage),
     AddData(AddDataMessage),
     AddPersistableNetworkPayload(AddPersistableNetworkPayloadMessage),
+    RemoveData(RemoveDataMessage),
+    RemoveMailboxData(RemoveMailboxDataMessage),
+    PreliminaryGetData(PreliminaryGetDataRequest),
+    GetUpdatedData(GetUpdatedDataRequest),
 }
 
 impl Handler<Receive<DataRouterDispatch>> for DataRouter {
@@ -213,28 +395,38 @@ impl Handler<Receive<DataRouterDispatch>> for DataRouter {
     ) {
         match dispatch {
             DataRouterDispatch::Bootstrap(data, persistable_network_payloads) => {
-                self.route_bootstrap_data(data, persistable_network_payloads)
+                self.route_bootstrap_data(origin, data, persistable_network_payloads);
             }
             DataRouterDispatch::RefreshOffer(msg) => {
-                let hash = msg.payload_hash();
+                let hash = match msg.payload_hash() {
+                    Some(hash) => hash,
+                    None => return self.report(origin, Offence::CorruptedData),
+                };
+                let mut verified = true;
                 if let Some(ref mut info) = self.sequenced_message_info.get_mut(&hash) {
-                    if info.sequence < msg.sequence_number
-                        && msg
+                    if info.sequence < msg.sequence_number {
+                        verified = msg
                             .verify(&*info.owner_pub_key, &info.original_payload)
-                            .is_some()
-                    {
-                        info.sequence = msg.sequence_number;
-                        info.last_delivery = SystemTime::now();
-                        Arbiter::spawn(
-                            self.offer_book
-                                .send(convert::refresh_offer(&msg))
-                                .then(self.handle_command_result(origin, msg)),
-                        );
+                            .is_some();
+                        if verified {
+                            info.sequence = msg.sequence_number;
+                            info.last_delivery = SystemTime::now();
+                            self.store.refresh_entry(&hash, &msg);
+                            Arbiter::spawn(
+                                self.offer_book
+                                    .send(convert::refresh_offer(&msg, hash))
+                                    .then(self.handle_command_result(origin, msg)),
+                            );
+                        }
                     }
                 }
+                if !verified {
+                    self.report(origin, Offence::InvalidSignature);
+                }
             }
             DataRouterDispatch::AddData(data) => {
                 self.route_storage_entry_wrapper(
+                    origin,
                     data.entry.clone(),
                     self.handle_command_result(origin, data),
                 );
@@ -246,10 +438,90 @@ impl Handler<Receive<DataRouterDispatch>> for DataRouter {
                     self.handle_command_result(origin, msg),
                 );
             }
+            DataRouterDispatch::RemoveData(msg) => {
+                self.route_remove_data(
+                    origin,
+                    msg.protected_storage_entry.clone(),
+                    self.handle_command_result(origin, msg),
+                );
+            }
+            DataRouterDispatch::RemoveMailboxData(msg) => {
+                self.route_remove_mailbox_data(
+                    origin,
+                    msg.protected_storage_entry.clone(),
+                    self.handle_command_result(origin, msg),
+                );
+            }
+            DataRouterDispatch::PreliminaryGetData(request) => {
+                let response = self.store.get_data_response(
+                    request.nonce,
+                    false,
+                    request.excluded_keys,
+                    &request.supported_capabilities,
+                );
+                self.peer_capabilities
+                    .insert(origin, (request.supported_capabilities, Instant::now()));
+                self.send_data_response(origin, response);
+            }
+            DataRouterDispatch::GetUpdatedData(request) => {
+                // The capabilities are only known if the peer sent a preliminary request first
+                let capabilities = self
+                    .peer_capabilities
+                    .remove(&origin)
+                    .map(|(capabilities, _)| capabilities)
+                    .unwrap_or_default();
+                let response = self.store.get_data_response(
+                    request.nonce,
+                    true,
+                    request.excluded_keys,
+                    &capabilities,
+                );
+                self.send_data_response(origin, response);
+            }
         }
     }
 }
 
+impl Handler<ReportMisbehaviourTo> for DataRouter {
+    type Result = ();
+    fn handle(
+        &mut self,
+        ReportMisbehaviourTo(recipient): ReportMisbehaviourTo,
+        _ctx: &mut Self::Context,
+    ) {
+        self.misbehaviour = Some(recipient);
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ResyncStats {
+    pub rounds: usize,
+    pub recovered_entries: usize,
+    pub recovered_payloads: usize,
+    pub last_resync: Option<SystemTime>,
+}
+pub struct GetResyncStats;
+impl Message for GetResyncStats {
+    type Result = ResyncStats;
+}
+impl Handler<GetResyncStats> for DataRouter {
+    type Result = MessageResult<GetResyncStats>;
+    fn handle(&mut self, _: GetResyncStats, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.resync_stats.clone())
+    }
+}
+
+pub struct ResyncVia(pub Recipient<RequestUpdatedData>);
+impl Message for ResyncVia {
+    type Result = ();
+}
+impl Handler<ResyncVia> for DataRouter {
+    type Result = ();
+    fn handle(&mut self, ResyncVia(recipient): ResyncVia, _ctx: &mut Self::Context) {
+        self.resync = Some(recipient);
+    }
+}
+
 impl PayloadExtractor for DataRouterDispatch {
     type Extraction = DataRouterDispatch;
     fn extract(msg: network_envelope::Message) -> Extract<Self::Extraction> {
@@ -271,6 +543,18 @@ impl PayloadExtractor for DataRouterDispatch {
             network_envelope::Message::AddPersistableNetworkPayloadMessage(msg) => {
                 Extract::Succeeded(DataRouterDispatch::AddPersistableNetworkPayload(msg))
             }
+            network_envelope::Message::RemoveDataMessage(msg) => {
+                Extract::Succeeded(DataRouterDispatch::RemoveData(msg))
+            }
+            network_envelope::Message::RemoveMailboxDataMessage(msg) => {
+                Extract::Succeeded(DataRouterDispatch::RemoveMailboxData(msg))
+            }
+            network_envelope::Message::PreliminaryGetDataRequest(msg) => {
+                Extract::Succeeded(DataRouterDispatch::PreliminaryGetData(msg))
+            }
+            network_envelope::Message::GetUpdatedDataRequest(msg) => {
+                Extract::Succeeded(DataRouterDispatch::GetUpdatedData(msg))
+            }
             _ => Extract::Failed(msg),
         }
     }
@@ -0,0 +1,348 @@
+use crate::bisq::{constants::Capability, payload::*, PersistentMessageHash, SequencedMessageHash};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Same limit Bisq's GetDataRequestHandler applies to each part of a response
+const MAX_ENTRIES: usize = 10_000;
+const DAY: u64 = 24 * 60 * 60;
+
+pub struct DataStore {
+    entries: HashMap<SequencedMessageHash, StorageEntryWrapper>,
+    payloads: HashMap<PersistentMessageHash, PersistableNetworkPayload>,
+    capabilities: Vec<i32>,
+}
+impl DataStore {
+    pub fn new(capabilities: Vec<i32>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            payloads: HashMap::new(),
+            capabilities,
+        }
+    }
+
+    pub fn add_entry(&mut self, hash: SequencedMessageHash, mut entry: StorageEntryWrapper) {
+        // Like Bisq, don't let a timestamp from the future extend the lifetime of an entry
+        let now = now_millis();
+        if let Some(entry) = protected_storage_entry_mut(&mut entry) {
+            entry.creation_time_stamp = entry.creation_time_stamp.min(now);
+        }
+        self.entries.insert(hash, entry);
+    }
+
+    pub fn remove_entry(&mut self, hash: &SequencedMessageHash) {
+        self.entries.remove(hash);
+    }
+
+    pub fn refresh_entry(&mut self, hash: &SequencedMessageHash, msg: &RefreshOfferMessage) {
+        let entry = match self.entries.get_mut(hash).and_then(|w| w.message.as_mut()) {
+            Some(storage_entry_wrapper::Message::ProtectedStorageEntry(entry)) => entry,
+            _ => return,
+        };
+        entry.sequence_number = msg.sequence_number;
+        entry.signature = msg.signature.clone();
+        entry.creation_time_stamp = now_millis();
+    }
+
+    pub fn prune_expired(&mut self) -> usize {
+        let now = SystemTime::now();
+        let before = self.entries.len();
+        self.entries.retain(|_, wrapper| !is_expired(wrapper, now));
+        before - self.entries.len()
+    }
+
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        self.entries
+            .keys()
+            .map(|hash| Vec::<u8>::from(*hash))
+            .chain(self.payloads.keys().map(|hash| Vec::<u8>::from(*hash)))
+            .collect()
+    }
+
+    pub fn contains_payload(&self, hash: &PersistentMessageHash) -> bool {
+        self.payloads.contains_key(hash)
+    }
+
+    pub fn add_payload(&mut self, hash: PersistentMessageHash, payload: PersistableNetworkPayload) {
+        self.payloads.insert(hash, payload);
+    }
+
+    pub fn get_data_response(
+        &self,
+        request_nonce: i32,
+        is_get_updated_data_response: bool,
+        excluded_keys: Vec<Vec<u8>>,
+        capabilities: &[i32],
+    ) -> GetDataResponse {
+        let excluded_keys: HashSet<Vec<u8>> = excluded_keys.into_iter().collect();
+        let supports = |required: Option<Capability>| {
+            required.map_or(true, |c| capabilities.contains(&(c as i32)))
+        };
+        let data_set: Vec<StorageEntryWrapper> = self
+            .entries
+            .iter()
+            .filter(|(hash, wrapper)| {
+                !excluded_keys.contains(&Vec::<u8>::from(**hash))
+                    && supports(entry_required_capability(wrapper))
+            })
+            .take(MAX_ENTRIES)
+            .map(|(_, wrapper)| wrapper.clone())
+            .collect();
+        let persistable_network_payload_items: Vec<PersistableNetworkPayload> = self
+            .payloads
+            .iter()
+            .filter(|(hash, payload)| {
+                !excluded_keys.contains(&Vec::<u8>::from(**hash))
+                    && supports(payload_required_capability(payload))
+            })
+            .take(MAX_ENTRIES)
+            .map(|(_, payload)| payload.clone())
+            .collect();
+        if data_set.len() == MAX_ENTRIES || persistable_network_payload_items.len() == MAX_ENTRIES {
+            info!("GetDataResponse truncated to {} entries", MAX_ENTRIES);
+        }
+        GetDataResponse {
+            request_nonce,
+            is_get_updated_data_response,
+            data_set,
+            supported_capabilities: self.capabilities.clone(),
+            persistable_network_payload_items,
+        }
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+}
+
+fn protected_storage_entry_mut(
+    wrapper: &mut StorageEntryWrapper,
+) -> Option<&mut ProtectedStorageEntry> {
+    match wrapper.message.as_mut()? {
+        storage_entry_wrapper::Message::ProtectedStorageEntry(entry) => Some(entry),
+        storage_entry_wrapper::Message::ProtectedMailboxStorageEntry(mailbox_entry) => {
+            mailbox_entry.entry.as_mut()
+        }
+    }
+}
+
+// The TTLs Bisq assigns to each kind of storage payload
+fn entry_ttl(payload: &storage_payload::Message) -> Duration {
+    Duration::from_secs(match payload {
+        storage_payload::Message::Alert(_) => 90 * DAY,
+        storage_payload::Message::Arbitrator(_) => 10 * DAY,
+        storage_payload::Message::Mediator(_) => 10 * DAY,
+        storage_payload::Message::Filter(_) => 180 * DAY,
+        storage_payload::Message::TradeStatistics(_) => 30 * DAY,
+        storage_payload::Message::MailboxStoragePayload(_) => 15 * DAY,
+        storage_payload::Message::OfferPayload(_) => 9 * 60,
+        storage_payload::Message::TempProposalPayload(_) => 30 * DAY,
+    })
+}
+
+fn is_expired(wrapper: &StorageEntryWrapper, now: SystemTime) -> bool {
+    let entry = match wrapper.protected_storage_entry() {
+        Some(entry) => entry,
+        None => return true,
+    };
+    let ttl = match entry
+        .storage_payload
+        .as_ref()
+        .and_then(|p| p.message.as_ref())
+    {
+        Some(payload) => entry_ttl(payload),
+        None => return true,
+    };
+    if entry.creation_time_stamp < 0 {
+        return true;
+    }
+    UNIX_EPOCH
+        .checked_add(Duration::from_millis(entry.creation_time_stamp as u64))
+        .and_then(|created_at| created_at.checked_add(ttl))
+        .map_or(true, |expires_at| expires_at <= now)
+}
+
+fn entry_required_capability(wrapper: &StorageEntryWrapper) -> Option<Capability> {
+    match wrapper
+        .protected_storage_entry()?
+        .storage_payload
+        .as_ref()?
+        .message
+        .as_ref()?
+    {
+        storage_payload::Message::Mediator(_) => Some(Capability::Mediation),
+        storage_payload::Message::TempProposalPayload(_) => Some(Capability::Proposal),
+        _ => None,
+    }
+}
+
+fn payload_required_capability(payload: &PersistableNetworkPayload) -> Option<Capability> {
+    match payload.message.as_ref()? {
+        persistable_network_payload::Message::ProposalPayload(_) => Some(Capability::Proposal),
+        persistable_network_payload::Message::BlindVotePayload(_) => Some(Capability::BlindVote),
+        persistable_network_payload::Message::SignedWitness(_) => {
+            Some(Capability::SignedAccountAgeWitness)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{ripemd160, sha256, Hash};
+
+    fn entry(payload: storage_payload::Message, created_ago: Duration) -> StorageEntryWrapper {
+        let created_at = SystemTime::now() - created_ago;
+        StorageEntryWrapper {
+            message: Some(storage_entry_wrapper::Message::ProtectedStorageEntry(
+                ProtectedStorageEntry {
+                    storage_payload: Some(StoragePayload {
+                        message: Some(payload),
+                    }),
+                    creation_time_stamp: created_at.duration_since(UNIX_EPOCH).unwrap().as_millis()
+                        as i64,
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    fn offer(created_ago: Duration) -> StorageEntryWrapper {
+        entry(
+            storage_payload::Message::OfferPayload(Default::default()),
+            created_ago,
+        )
+    }
+
+    fn mediator() -> StorageEntryWrapper {
+        entry(
+            storage_payload::Message::Mediator(Default::default()),
+            Duration::from_secs(0),
+        )
+    }
+
+    fn entry_hash(n: u32) -> SequencedMessageHash {
+        SequencedMessageHash::new(sha256::Hash::hash(&n.to_be_bytes()))
+    }
+
+    fn payload_hash(n: u32) -> PersistentMessageHash {
+        PersistentMessageHash::new(ripemd160::Hash::hash(&n.to_be_bytes()))
+    }
+
+    fn trade() -> PersistableNetworkPayload {
+        PersistableNetworkPayload {
+            message: Some(persistable_network_payload::Message::TradeStatistics2(
+                Default::default(),
+            )),
+        }
+    }
+
+    fn signed_witness() -> PersistableNetworkPayload {
+        PersistableNetworkPayload {
+            message: Some(persistable_network_payload::Message::SignedWitness(
+                Default::default(),
+            )),
+        }
+    }
+
+    #[test]
+    fn excludes_keys() {
+        let mut store = DataStore::new(Vec::new());
+        store.add_entry(entry_hash(1), offer(Duration::from_secs(0)));
+        store.add_entry(entry_hash(2), offer(Duration::from_secs(0)));
+        store.add_payload(payload_hash(1), trade());
+        store.add_payload(payload_hash(2), trade());
+
+        let excluded = vec![entry_hash(1).into(), payload_hash(2).into()];
+        let response = store.get_data_response(1, false, excluded, &[]);
+        assert_eq!(response.request_nonce, 1);
+        assert_eq!(response.data_set.len(), 1);
+        assert_eq!(response.persistable_network_payload_items.len(), 1);
+        let response = store.get_data_response(2, true, store.keys(), &[]);
+        assert!(response.data_set.is_empty());
+        assert!(response.persistable_network_payload_items.is_empty());
+    }
+
+    #[test]
+    fn keys_cover_entries_and_payloads() {
+        let mut store = DataStore::new(Vec::new());
+        store.add_entry(entry_hash(1), offer(Duration::from_secs(0)));
+        store.add_entry(entry_hash(2), offer(Duration::from_secs(0)));
+        store.add_payload(payload_hash(1), trade());
+        store.remove_entry(&entry_hash(2));
+
+        let mut keys = store.keys();
+        keys.sort();
+        let mut expected: Vec<Vec<u8>> = vec![entry_hash(1).into(), payload_hash(1).into()];
+        expected.sort();
+        assert_eq!(keys, expected);
+        // Bisq keys entries by their sha256 and payloads by their ripemd160 hash
+        let mut lengths: Vec<usize> = keys.iter().map(Vec::len).collect();
+        lengths.sort();
+        assert_eq!(lengths, vec![20, 32]);
+    }
+
+    #[test]
+    fn filters_by_capability() {
+        let mut store = DataStore::new(Vec::new());
+        store.add_entry(entry_hash(1), mediator());
+        store.add_payload(payload_hash(1), signed_witness());
+
+        let response = store.get_data_response(1, false, Vec::new(), &[]);
+        assert!(response.data_set.is_empty());
+        assert!(response.persistable_network_payload_items.is_empty());
+        let capabilities = [
+            Capability::Mediation as i32,
+            Capability::SignedAccountAgeWitness as i32,
+        ];
+        let response = store.get_data_response(1, false, Vec::new(), &capabilities);
+        assert_eq!(response.data_set.len(), 1);
+        assert_eq!(
+            response.persistable_network_payload_items,
+            vec![signed_witness()]
+        );
+    }
+
+    #[test]
+    fn truncates_response() {
+        let mut store = DataStore::new(Vec::new());
+        for n in 0..=MAX_ENTRIES as u32 {
+            store.add_payload(payload_hash(n), trade());
+        }
+        let response = store.get_data_response(1, false, Vec::new(), &[]);
+        assert_eq!(
+            response.persistable_network_payload_items.len(),
+            MAX_ENTRIES
+        );
+    }
+
+    #[test]
+    fn prunes_expired_entries() {
+        let mut store = DataStore::new(Vec::new());
+        store.add_entry(entry_hash(1), offer(Duration::from_secs(60)));
+        store.add_entry(entry_hash(2), offer(Duration::from_secs(10 * 60)));
+        store.add_entry(entry_hash(3), mediator());
+        assert_eq!(store.prune_expired(), 1);
+        let response =
+            store.get_data_response(1, false, Vec::new(), &[Capability::Mediation as i32]);
+        assert_eq!(response.data_set.len(), 2);
+        assert!(!store.keys().contains(&entry_hash(2).into()));
+    }
+
+    #[test]
+    fn clamps_future_timestamps() {
+        let mut store = DataStore::new(Vec::new());
+        let mut future = offer(Duration::from_secs(0));
+        if let Some(entry) = protected_storage_entry_mut(&mut future) {
+            entry.creation_time_stamp = i64::max_value();
+        }
+        store.add_entry(entry_hash(1), future);
+        let response = store.get_data_response(1, false, Vec::new(), &[]);
+        let stored = response.data_set[0].protected_storage_entry().unwrap();
+        assert!(stored.creation_time_stamp <= now_millis());
+    }
+}
@@ -0,0 +1,103 @@
+use super::data_router::*;
+use crate::{
+    bisq::{
+        constants::local_capabilities,
+        payload::{Extract, PayloadExtractor},
+    },
+    domain::{
+        offer::{message::GetOpenOffers, OfferBook},
+        price_feed::PriceFeed,
+        statistics::StatsCache,
+    },
+    p2p::{
+        dispatch::{capture, Receive},
+        Broadcaster,
+    },
+    prelude::{future::Either, *},
+};
+use std::{path::Path, process};
+
+const TARGET_PRECISION: u32 = 8;
+
+// Offers expire relative to their creation time so an old capture may end up without any
+pub fn replay(path: &Path) {
+    let records = match capture::read(path) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Couldn't read capture {:?}: {:?}", path, e);
+            process::exit(1);
+        }
+    };
+    if let (Some(first), Some(last)) = (records.first(), records.last()) {
+        info!(
+            "Replaying {} messages received between {:?} and {:?}",
+            records.len(),
+            first.received_at,
+            last.received_at
+        );
+    }
+
+    let mut sys = System::new("risq-replay");
+    let offer_book = OfferBook::start(PriceFeed::offline());
+    let stats_cache = StatsCache::new();
+    let data_router = DataRouter::start(
+        offer_book.clone(),
+        Broadcaster::start(),
+        stats_cache.clone(),
+        local_capabilities(false),
+        // There are no peers to resync from
+        None,
+    );
+    sys.block_on(stream::iter_ok(records).for_each(move |record| {
+        match DataRouterDispatch::extract(record.message) {
+            Extract::Succeeded(dispatch) => Either::A(
+                data_router
+                    .send(Receive(record.connection_id, dispatch))
+                    .map_err(|_| ()),
+            ),
+            Extract::Failed(_) => Either::B(future::ok(())),
+        }
+    }))
+    .expect("DataRouter stopped during replay");
+
+    let offers = sys
+        .block_on(offer_book.send(GetOpenOffers))
+        .expect("OfferBook stopped during replay");
+    println!("OPEN OFFERS ({})", offers.len());
+    for offer in offers.values() {
+        println!(
+            "{} {} - {:?} {} {}({}) {}",
+            offer.market.pair,
+            String::from(offer.id.clone()),
+            offer.direction,
+            offer.display_price.format(TARGET_PRECISION),
+            offer.amount.total.format(TARGET_PRECISION),
+            offer.amount.min.format(TARGET_PRECISION),
+            offer.payment_method_id
+        );
+    }
+    print_trades(&mut sys, stats_cache);
+}
+
+#[cfg(feature = "statistics")]
+fn print_trades(sys: &mut SystemRunner, stats_cache: Option<StatsCache>) {
+    let stats = sys
+        .block_on(stats_cache.expect("StatsCache missing").inner())
+        .expect("StatsCache unavailable");
+    println!("TRADES ({})", stats.trades().count());
+    for trade in stats.trades() {
+        println!(
+            "{} {} - {:?} {} {} {}",
+            trade.market.pair,
+            String::from(trade.offer_id.clone()),
+            trade.direction,
+            trade.price.format(TARGET_PRECISION),
+            trade.amount.format(TARGET_PRECISION),
+            trade.payment_method_id
+        );
+    }
+}
+#[cfg(not(feature = "statistics"))]
+fn print_trades(_: &mut SystemRunner, _: Option<StatsCache>) {
+    println!("TRADES (built without the statistics feature)");
+}
@@ -0,0 +1,111 @@
+use crate::{
+    bisq::constants::CloseConnectionReason,
+    p2p::{message::CloseAll, server::event::ShuttingDown},
+    prelude::*,
+};
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+use std::time::Duration;
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Shutdown {
+    close_all: Recipient<CloseAll>,
+    peers: Recipient<ShuttingDown>,
+    server: Recipient<ShuttingDown>,
+    in_progress: bool,
+}
+impl Shutdown {
+    pub fn start(
+        close_all: Recipient<CloseAll>,
+        peers: Recipient<ShuttingDown>,
+        server: Recipient<ShuttingDown>,
+    ) -> Addr<Self> {
+        Self {
+            close_all,
+            peers,
+            server,
+            in_progress: false,
+        }
+        .start()
+    }
+}
+impl Actor for Shutdown {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ProcessSignals::from_registry().do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+impl Handler<Signal> for Shutdown {
+    type Result = ();
+    fn handle(&mut self, Signal(signal): Signal, ctx: &mut Self::Context) {
+        match signal {
+            SignalType::Int | SignalType::Term | SignalType::Quit => (),
+            _ => return,
+        }
+        if self.in_progress {
+            warn!("Received {:?} again, stopping immediately", signal);
+            System::current().stop();
+            return;
+        }
+        self.in_progress = true;
+        info!("Received {:?}, shutting down", signal);
+
+        // Peers stop dialing before the connections are closed,
+        // and peers should hear about the shutdown before the onion disappears
+        let close_all = self.close_all.clone();
+        let server = self.server.clone();
+        ctx.spawn(fut::wrap_future(
+            self.peers
+                .send(ShuttingDown)
+                .then(move |_| close_all.send(CloseAll(CloseConnectionReason::AppShutDown)))
+                .then(move |_| server.send(ShuttingDown).then(|_| Ok::<(), ()>(())))
+                .timeout(SHUTDOWN_TIMEOUT)
+                .then(|res| {
+                    if res.is_err() {
+                        warn!("Shutdown didn't complete within {:?}", SHUTDOWN_TIMEOUT);
+                    }
+                    System::current().stop();
+                    Ok(())
+                }),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Records which step of the shutdown reached it
+    struct Step(&'static str, Arc<Mutex<Vec<&'static str>>>);
+    impl Actor for Step {
+        type Context = Context<Self>;
+    }
+    impl Handler<ShuttingDown> for Step {
+        type Result = ();
+        fn handle(&mut self, _: ShuttingDown, _ctx: &mut Self::Context) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+    impl Handler<CloseAll> for Step {
+        type Result = Result<(), ()>;
+        fn handle(&mut self, _: CloseAll, _ctx: &mut Self::Context) -> Self::Result {
+            self.1.lock().unwrap().push(self.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn peers_stop_before_connections_close() {
+        let sys = System::new("shutdown");
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Shutdown::start(
+            Step("close", steps.clone()).start().recipient(),
+            Step("peers", steps.clone()).start().recipient(),
+            Step("server", steps.clone()).start().recipient(),
+        );
+        shutdown.do_send(Signal(SignalType::Term));
+        sys.run().unwrap();
+        assert_eq!(*steps.lock().unwrap(), vec!["peers", "close", "server"]);
+    }
+}
@@ -0,0 +1,422 @@
+//! Runs a small regtest network in process: the two seed nodes from `seed_nodes`
+//! plus regular nodes, all risq daemons talking over localhost without tor.
+//! The removal checks drive a DataRouter directly and need no network.
+
+use super::*;
+use crate::{
+    bisq::{constants::seed_nodes, payload::*, Sha256},
+    domain::{
+        offer::{message::GetOpenOffers, OfferId},
+        price_feed::PriceFeed,
+    },
+    p2p::{
+        dispatch::{Dispatch, Dispatcher, Receive},
+        transport::Tcp,
+        Connection, ConnectionId, Payload, DEFAULT_MAX_FRAME_SIZE,
+    },
+};
+use openssl::{
+    dsa::Dsa,
+    pkey::{PKey, Private},
+    sign::Signer,
+};
+use std::{
+    convert::TryFrom,
+    env, fs,
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::timer::Delay;
+use uuid::Uuid;
+
+const CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(180);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const NODE_PORTS: [u16; 2] = [2012, 2022];
+const OFFER_ID: &str = "integration-offer";
+const TRADE_ID: &str = "integration-trade";
+
+// Removes the node's risq home once the test is done, even if it failed
+struct TempHome(PathBuf);
+impl TempHome {
+    fn new() -> Self {
+        TempHome(env::temp_dir().join(format!("risq-test-{}", Uuid::new_v4())))
+    }
+}
+impl Drop for TempHome {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn config(port: u16, seed_node: bool, home: &TempHome) -> DaemonConfig {
+    DaemonConfig {
+        api_port: 0,
+        server_port: port,
+        listen_addr: [127, 0, 0, 1].into(),
+        advertised_addr: Some("127.0.0.1".to_string()),
+        network: BaseCurrencyNetwork::BtcRegtest,
+        tor_config: None,
+        tor_proxy: None,
+        risq_home: home.0.clone(),
+        max_peer_age: Duration::from_secs(60 * 60),
+        bootstrap_seeds: 2,
+        ban_period: Duration::from_secs(60 * 60),
+        max_failed_pings: 3,
+        bundling: BundleConfig {
+            window: Duration::from_millis(50),
+            max_size: 100,
+        },
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        capture_file: None,
+        seed_node,
+        resync_interval: Duration::from_secs(10 * 60),
+    }
+}
+
+fn local(port: u16) -> NodeAddress {
+    NodeAddress {
+        host_name: "127.0.0.1".to_string(),
+        port: port as i32,
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as i64
+}
+
+// Stands in for a bisq client publishing data through the node it is connected to
+#[derive(Clone)]
+struct Ignore;
+impl Dispatcher for Ignore {
+    fn dispatch(&self, _: ConnectionId, _: network_envelope::Message) -> Dispatch {
+        Dispatch::Consumed
+    }
+}
+
+struct Owner {
+    key: PKey<Private>,
+    pub_key: Vec<u8>,
+}
+impl Owner {
+    fn new() -> Self {
+        let dsa = Dsa::generate(1024).unwrap();
+        let pub_key = dsa.public_key_to_der().unwrap();
+        Self {
+            key: PKey::from_dsa(dsa).unwrap(),
+            pub_key,
+        }
+    }
+
+    // Returns the signed hash and its signature
+    fn sign(&self, payload: &StoragePayload, sequence_number: i32) -> (Vec<u8>, Vec<u8>) {
+        let hash = DataAndSeqNrPair {
+            payload: Some(payload.clone()),
+            sequence_number,
+        }
+        .sha256()
+        .into_inner();
+        let signature = Signer::new_without_digest(&self.key)
+            .unwrap()
+            .sign_oneshot_to_vec(&hash)
+            .unwrap();
+        (hash.to_vec(), signature)
+    }
+
+    fn offer(&self) -> StoragePayload {
+        StoragePayload {
+            message: Some(storage_payload::Message::OfferPayload(OfferPayload {
+                id: OFFER_ID.to_string(),
+                date: now_millis(),
+                pub_key_ring: Some(PubKeyRing {
+                    signature_pub_key_bytes: self.pub_key.clone(),
+                    encryption_pub_key_bytes: Vec::new(),
+                }),
+                direction: offer_payload::Direction::Buy as i32,
+                price: 100_000_000,
+                amount: 1_000_000,
+                min_amount: 1_000_000,
+                base_currency_code: "BTC".to_string(),
+                counter_currency_code: "EUR".to_string(),
+                payment_method_id: "SEPA".to_string(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn entry(&self, payload: &StoragePayload, sequence_number: i32) -> ProtectedStorageEntry {
+        let (_, signature) = self.sign(payload, sequence_number);
+        ProtectedStorageEntry {
+            storage_payload: Some(payload.clone()),
+            owner_pub_key_bytes: self.pub_key.clone(),
+            sequence_number,
+            signature,
+            creation_time_stamp: now_millis(),
+        }
+    }
+
+    fn add(&self, payload: &StoragePayload) -> AddDataMessage {
+        AddDataMessage {
+            entry: Some(StorageEntryWrapper {
+                message: Some(storage_entry_wrapper::Message::ProtectedStorageEntry(
+                    self.entry(payload, 1),
+                )),
+            }),
+        }
+    }
+
+    fn refresh(&self, payload: &StoragePayload, sequence_number: i32) -> RefreshOfferMessage {
+        let (hash_of_data_and_seq_nr, signature) = self.sign(payload, sequence_number);
+        RefreshOfferMessage {
+            hash_of_data_and_seq_nr,
+            signature,
+            hash_of_payload: Vec::from(payload.bisq_hash()),
+            sequence_number,
+        }
+    }
+
+    fn remove(&self, payload: &StoragePayload, sequence_number: i32) -> RemoveDataMessage {
+        RemoveDataMessage {
+            protected_storage_entry: Some(self.entry(payload, sequence_number)),
+        }
+    }
+}
+
+fn trade_statistics() -> AddPersistableNetworkPayloadMessage {
+    AddPersistableNetworkPayloadMessage {
+        payload: Some(PersistableNetworkPayload {
+            message: Some(persistable_network_payload::Message::TradeStatistics2(
+                TradeStatistics2 {
+                    base_currency: "BTC".to_string(),
+                    counter_currency: "EUR".to_string(),
+                    direction: offer_payload::Direction::Sell as i32,
+                    trade_price: 100_000_000,
+                    trade_amount: 1_000_000,
+                    trade_date: now_millis(),
+                    payment_method_id: "SEPA".to_string(),
+                    offer_id: TRADE_ID.to_string(),
+                    hash: vec![7; 20],
+                    ..Default::default()
+                },
+            )),
+        }),
+    }
+}
+
+type Check = Box<dyn Future<Item = bool, Error = ()>>;
+
+fn on_all_nodes<F, C>(nodes: &[Node], check: F) -> Check
+where
+    F: Fn(&Node) -> C,
+    C: Future<Item = bool, Error = ()> + 'static,
+{
+    let checks: Vec<C> = nodes.iter().map(check).collect();
+    Box::new(future::join_all(checks).map(|results| results.into_iter().all(|ok| ok)))
+}
+
+fn offer_sequence(offer_book: &Addr<OfferBook>) -> impl Future<Item = Option<i32>, Error = ()> {
+    offer_book
+        .send(GetOpenOffers)
+        .map(|offers| {
+            offers
+                .values()
+                .find(|offer| offer.id == OfferId::from(OFFER_ID.to_string()))
+                .map(|offer| i32::from(offer.latest_sequence))
+        })
+        .map_err(|_| ())
+}
+
+fn eventually(sys: &mut SystemRunner, what: &str, mut check: impl FnMut() -> Check) {
+    let deadline = Instant::now() + CONVERGENCE_TIMEOUT;
+    while sys.block_on(check()) != Ok(true) {
+        assert!(
+            Instant::now() < deadline,
+            "{} didn't converge within {:?}",
+            what,
+            CONVERGENCE_TIMEOUT
+        );
+        sys.block_on(Delay::new(Instant::now() + POLL_INTERVAL))
+            .unwrap();
+    }
+}
+
+fn start_node(port: u16, seed_node: bool, homes: &mut Vec<TempHome>) -> Node {
+    let home = TempHome::new();
+    let node = start(config(port, seed_node, &home), PriceFeed::offline());
+    homes.push(home);
+    node
+}
+
+// Binds the fixed regtest ports and can take minutes, run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn regtest_network_converges() {
+    let mut sys = System::new("regtest");
+    let mut homes = Vec::new();
+    let seeds = seed_nodes(&BaseCurrencyNetwork::BtcRegtest);
+    let mut nodes: Vec<Node> = seeds
+        .iter()
+        .map(|seed| start_node(seed.port as u16, true, &mut homes))
+        .collect();
+    nodes.extend(
+        NODE_PORTS
+            .iter()
+            .map(|port| start_node(*port, false, &mut homes)),
+    );
+
+    eventually(&mut sys, "joining the network", || {
+        on_all_nodes(&nodes, |node| {
+            node.network_info
+                .send(GetNetworkInfo)
+                .map(|info| info.map_or(false, |info| !info.connections.is_empty()))
+                .map_err(|_| ())
+        })
+    });
+
+    let (_, publisher) = sys
+        .block_on(Connection::open(
+            local(NODE_PORTS[0]),
+            BaseCurrencyNetwork::BtcRegtest.into(),
+            Ignore,
+            &Tcp,
+            DEFAULT_MAX_FRAME_SIZE,
+        ))
+        .expect("Couldn't connect to node");
+    let publish = |sys: &mut SystemRunner, msg: network_envelope::Message| {
+        sys.block_on(publisher.send(Payload(msg)).flatten())
+            .expect("Couldn't publish")
+    };
+    let owner = Owner::new();
+    let offer = owner.offer();
+
+    publish(&mut sys, owner.add(&offer).into());
+    eventually(&mut sys, "adding the offer", || {
+        on_all_nodes(&nodes, |node| {
+            offer_sequence(&node.offer_book).map(|seq| seq == Some(1))
+        })
+    });
+
+    publish(&mut sys, owner.refresh(&offer, 2).into());
+    eventually(&mut sys, "refreshing the offer", || {
+        on_all_nodes(&nodes, |node| {
+            offer_sequence(&node.offer_book).map(|seq| seq == Some(2))
+        })
+    });
+
+    publish(&mut sys, owner.remove(&offer, 3).into());
+    eventually(&mut sys, "removing the offer", || {
+        on_all_nodes(&nodes, |node| {
+            offer_sequence(&node.offer_book).map(|seq| seq.is_none())
+        })
+    });
+
+    publish(&mut sys, trade_statistics().into());
+    #[cfg(feature = "statistics")]
+    eventually(&mut sys, "trade statistics", || {
+        on_all_nodes(&nodes, |node| {
+            node.stats_cache
+                .as_ref()
+                .expect("StatsCache missing")
+                .inner()
+                .map(|stats| {
+                    stats
+                        .trades()
+                        .any(|trade| trade.offer_id == OfferId::from(TRADE_ID.to_string()))
+                })
+        })
+    });
+
+    // Every regular node learns about the others through the seeds
+    let regular: Vec<NodeAddress> = NODE_PORTS.iter().map(|port| local(*port)).collect();
+    eventually(&mut sys, "peer exchange", || {
+        let regular = regular.clone();
+        on_all_nodes(&nodes[seeds.len()..], move |node| {
+            let regular = regular.clone();
+            node.network_info
+                .send(GetNetworkInfo)
+                .map(move |info| {
+                    info.map_or(false, |info| {
+                        regular
+                            .iter()
+                            .filter(|addr| info.local_addr.as_ref() != Some(*addr))
+                            .all(|addr| info.peers.iter().any(|peer| &peer.addr == addr))
+                    })
+                })
+                .map_err(|_| ())
+        })
+    });
+}
+
+#[test]
+fn verify_removal_checks_owner_and_signature() {
+    let owner = Owner::new();
+    let offer = owner.offer();
+    let removal = owner.entry(&offer, 2);
+    assert_eq!(
+        removal.verify_removal(&owner.pub_key),
+        Some(offer.bisq_hash())
+    );
+    assert_eq!(removal.verify_removal(&Owner::new().pub_key), None);
+    let mut tampered = removal;
+    tampered.sequence_number = 3;
+    assert_eq!(tampered.verify_removal(&owner.pub_key), None);
+}
+
+#[test]
+fn removes_offer_only_with_newer_signed_removal() {
+    let mut sys = System::new("removal");
+    let offer_book = OfferBook::start(PriceFeed::offline());
+    let data_router = DataRouter::start(
+        offer_book.clone(),
+        Broadcaster::start(),
+        StatsCache::new(),
+        local_capabilities(false),
+        None,
+    );
+    let origin = ConnectionId::try_from(&[1u8; 16][..]).unwrap();
+    let route = |sys: &mut SystemRunner, dispatch: DataRouterDispatch| {
+        sys.block_on(data_router.send(Receive(origin, dispatch)))
+            .unwrap()
+    };
+    let owner = Owner::new();
+    let offer = owner.offer();
+
+    route(&mut sys, DataRouterDispatch::AddData(owner.add(&offer)));
+    eventually(&mut sys, "adding the offer", || {
+        Box::new(offer_sequence(&offer_book).map(|seq| seq == Some(1)))
+    });
+
+    // A stale, a foreign and a tampered removal must all leave the offer alone
+    route(
+        &mut sys,
+        DataRouterDispatch::RemoveData(owner.remove(&offer, 1)),
+    );
+    route(
+        &mut sys,
+        DataRouterDispatch::RemoveData(Owner::new().remove(&offer, 2)),
+    );
+    let mut tampered = owner.remove(&offer, 2);
+    tampered
+        .protected_storage_entry
+        .as_mut()
+        .unwrap()
+        .sequence_number = 3;
+    route(&mut sys, DataRouterDispatch::RemoveData(tampered));
+    route(
+        &mut sys,
+        DataRouterDispatch::RefreshOffer(owner.refresh(&offer, 2)),
+    );
+    eventually(&mut sys, "refreshing the offer", || {
+        Box::new(offer_sequence(&offer_book).map(|seq| seq == Some(2)))
+    });
+
+    route(
+        &mut sys,
+        DataRouterDispatch::RemoveData(owner.remove(&offer, 3)),
+    );
+    eventually(&mut sys, "removing the offer", || {
+        Box::new(offer_sequence(&offer_book).map(|seq| seq.is_none()))
+    });
+}
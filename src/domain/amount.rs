@@ -25,7 +25,7 @@ impl NumberWithPrecision {
         let mut rest_amount = self.base_amount;
 
         if target_precision > self.precision {
-            rest_amount = rest_amount * 10_u64.pow(target_precision - self.precision);
+            rest_amount = rest_amount.saturating_mul(10_u64.pow(target_precision - self.precision));
         } else if self.precision > target_precision {
             rest_amount = rest_amount / 10_u64.pow(self.precision - target_precision);
         }
@@ -49,7 +49,7 @@ impl NumberWithPrecision {
     pub fn with_precision(&self, target_precision: u32) -> Self {
         let mut rest_amount = self.base_amount;
         if target_precision > self.precision {
-            rest_amount = rest_amount * 10_u64.pow(target_precision - self.precision);
+            rest_amount = rest_amount.saturating_mul(10_u64.pow(target_precision - self.precision));
         } else if self.precision > target_precision {
             rest_amount = rest_amount / 10_u64.pow(self.precision - target_precision);
         }
@@ -98,11 +98,12 @@ impl Mul for NumberWithPrecision {
                 break;
             }
         }
-        let mut res = left_value * right_value;
+        // Amounts come from peers, so saturate instead of overflowing
+        let mut res = left_value.saturating_mul(right_value);
         if res_precision > target_precision {
             res = res / 10_u64.pow(res_precision - right_precision);
         } else if res_precision < target_precision {
-            res = res * 10_u64.pow(right_precision - res_precision);
+            res = res.saturating_mul(10_u64.pow(right_precision - res_precision));
         }
         NumberWithPrecision::new(res, target_precision)
     }
@@ -138,7 +139,9 @@ impl AddAssign for NumberWithPrecision {
     fn add_assign(&mut self, other: Self) {
         let target_precision = self.precision.max(other.precision);
         *self = self.with_precision(target_precision);
-        self.base_amount += other.with_precision(target_precision).base_amount;
+        self.base_amount = self
+            .base_amount
+            .saturating_add(other.with_precision(target_precision).base_amount);
     }
 }
 
@@ -183,6 +186,13 @@ mod tests {
         assert!(&low_volume.format(8) == "0.90000000");
     }
 
+    #[test]
+    fn multiply_saturates() {
+        let huge = NumberWithPrecision::new(u64::max_value(), 8);
+        let volume = huge * NumberWithPrecision::new(3, 4);
+        assert!(volume == NumberWithPrecision::new(u64::max_value() / 100_000_000, 8));
+    }
+
     #[test]
     fn add_assign() {
         let mut amount = NumberWithPrecision::new(0, 8);
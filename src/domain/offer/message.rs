@@ -15,6 +15,14 @@ impl Message for RefreshOffer {
     type Result = CommandResult;
 }
 
+pub struct RemoveOffer {
+    pub bisq_hash: SequencedMessageHash,
+    pub sequence: OfferSequence,
+}
+impl Message for RemoveOffer {
+    type Result = CommandResult;
+}
+
 pub struct GetOpenOffers;
 impl Message for GetOpenOffers {
     type Result = Arc<HashMap<SequencedMessageHash, OpenOffer>>;
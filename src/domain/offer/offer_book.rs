@@ -100,6 +100,27 @@ impl Handler<RefreshOffer> for OfferBook {
         MessageResult(CommandResult::Ignored)
     }
 }
+impl Handler<RemoveOffer> for OfferBook {
+    type Result = MessageResult<RemoveOffer>;
+    fn handle(
+        &mut self,
+        RemoveOffer {
+            bisq_hash,
+            sequence,
+        }: RemoveOffer,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        match self.open_offers.get(&bisq_hash) {
+            Some(offer) if offer.latest_sequence < sequence => {
+                info!("Removing {:?}", offer.id);
+                let offers = Arc::make_mut(&mut self.open_offers);
+                offers.remove(&bisq_hash);
+                MessageResult(CommandResult::Accepted)
+            }
+            _ => MessageResult(CommandResult::Ignored),
+        }
+    }
+}
 
 impl Handler<GetOpenOffers> for OfferBook {
     type Result = MessageResult<GetOpenOffers>;
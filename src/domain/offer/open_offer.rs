@@ -31,6 +31,11 @@ impl From<i32> for OfferSequence {
         OfferSequence(s)
     }
 }
+impl From<OfferSequence> for i32 {
+    fn from(s: OfferSequence) -> Self {
+        s.0
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OfferDirection {
@@ -63,7 +68,7 @@ pub struct OpenOffer {
     pub display_price: NumberWithPrecision,
     pub display_volume: NumberWithPrecision,
 
-    pub(super) latest_sequence: OfferSequence,
+    pub(crate) latest_sequence: OfferSequence,
 
     price: OfferPrice,
     expires_at: SystemTime,
@@ -6,6 +6,7 @@ use reqwest::{r#async::Client, Proxy};
 use serde::{self, Deserialize};
 use std::{
     collections::HashMap,
+    net::SocketAddr,
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
@@ -24,20 +25,21 @@ impl Actor for PriceFeed {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        if self.nodes.is_empty() {
+            return;
+        }
         self.update_prices(ctx);
         ctx.run_interval(*LOOP_INTERVAL, |feed, ctx| feed.update_prices(ctx));
     }
 }
 impl PriceFeed {
-    pub fn start(proxy_port: Option<u16>) -> Addr<PriceFeed> {
-        let client = if proxy_port.is_some() {
+    pub fn start(proxy: Option<SocketAddr>) -> Addr<PriceFeed> {
+        let client = if let Some(proxy) = proxy {
             Client::builder()
                 .proxy(
-                    Proxy::http(&format!(
-                        "socks5h://127.0.0.1:{}",
-                        proxy_port.unwrap().to_string()
-                    ))
-                    .expect("Couldn't set proxy"),
+                    // Credentials of its own keep the price feed off the circuits used by peers
+                    Proxy::http(&format!("socks5h://risq:price-feed@{}", proxy))
+                        .expect("Couldn't set proxy"),
                 )
                 .build()
                 .expect("Couldn't create client")
@@ -47,10 +49,21 @@ impl PriceFeed {
         PriceFeed {
             client,
             price_data: Arc::new(HashMap::new()),
-            nodes: constants::price_nodes(proxy_port.is_some()),
+            nodes: constants::price_nodes(proxy.is_some()),
+        }
+        .start()
+    }
+
+    // Never fetches prices, for running the domain without networking
+    pub fn offline() -> Addr<PriceFeed> {
+        PriceFeed {
+            client: Client::new(),
+            price_data: Arc::new(HashMap::new()),
+            nodes: Vec::new(),
         }
         .start()
     }
+
     fn update_prices(&mut self, ctx: &mut Context<Self>) {
         let node_index: usize = thread_rng().gen::<usize>() % self.nodes.len();
         let url = format!("{}/getAllMarketPrices", self.nodes[node_index]);
@@ -1,12 +1,16 @@
-use crate::prelude::{
-    sync::{
-        mpsc::error::{RecvError, SendError},
-        oneshot,
+use crate::{
+    p2p::SocksError,
+    prelude::{
+        sync::{
+            mpsc::error::{RecvError, SendError},
+            oneshot,
+        },
+        MailboxError,
     },
-    MailboxError,
 };
 use prost::{DecodeError, EncodeError};
 use std::io;
+use tokio::timer::{self, timeout};
 
 #[derive(Debug)]
 pub enum Error {
@@ -17,6 +21,14 @@ pub enum Error {
     MailboxError(MailboxError),
     SendMPSCError,
     ReceiveMPSCError,
+    RequestTimeout,
+    // The timer driving a timeout failed, the request itself may be fine
+    Timer(timer::Error),
+    UnexpectedResponse,
+    ConnectTimeout,
+    Socks(SocksError),
+    FrameTooLarge(u64),
+    NoPeerConnected,
 }
 
 impl From<io::Error> for Error {
@@ -24,6 +36,11 @@ impl From<io::Error> for Error {
         Error::IoError(err)
     }
 }
+impl From<SocksError> for Error {
+    fn from(err: SocksError) -> Self {
+        Error::Socks(err)
+    }
+}
 impl From<DecodeError> for Error {
     fn from(err: DecodeError) -> Self {
         Error::Decode(err)
@@ -54,3 +71,32 @@ impl From<oneshot::error::RecvError> for Error {
         Error::ReceiveOneshotError
     }
 }
+impl From<timeout::Error<Error>> for Error {
+    fn from(err: timeout::Error<Error>) -> Self {
+        if err.is_timer() {
+            return Error::Timer(err.into_timer().expect("Timer error"));
+        }
+        err.into_inner().unwrap_or(Error::RequestTimeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_errors_are_not_timeouts() {
+        match Error::from(timeout::Error::<Error>::elapsed()) {
+            Error::RequestTimeout => (),
+            e => panic!("Expected RequestTimeout, got {:?}", e),
+        }
+        match Error::from(timeout::Error::<Error>::timer(timer::Error::shutdown())) {
+            Error::Timer(e) => assert!(e.is_shutdown()),
+            e => panic!("Expected Timer, got {:?}", e),
+        }
+        match Error::from(timeout::Error::inner(Error::NoPeerConnected)) {
+            Error::NoPeerConnected => (),
+            e => panic!("Expected NoPeerConnected, got {:?}", e),
+        }
+    }
+}
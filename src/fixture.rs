@@ -0,0 +1,300 @@
+use crate::{
+    bisq::{
+        constants::{local_capabilities, seed_nodes, BaseCurrencyNetwork},
+        payload::*,
+    },
+    p2p::{
+        dispatch::*, transport::Duplex, Connection, ConnectionId, Payload, DEFAULT_MAX_FRAME_SIZE,
+    },
+    prelude::{net::TcpListener, *},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+// Answers like a seed node, but only ever serves the recorded GetDataResponse
+pub struct FixtureSeed {
+    network: BaseCurrencyNetwork,
+    data: GetDataResponse,
+    connections: HashMap<ConnectionId, Addr<Connection>>,
+}
+impl Actor for FixtureSeed {
+    type Context = Context<Self>;
+}
+impl FixtureSeed {
+    pub fn start<S>(network: BaseCurrencyNetwork, data: GetDataResponse, incoming: S) -> Addr<Self>
+    where
+        S: Stream<Item = Box<dyn Duplex>, Error = io::Error> + 'static,
+    {
+        Self::create(move |ctx| {
+            ctx.add_stream(incoming);
+            Self {
+                network,
+                data,
+                connections: HashMap::new(),
+            }
+        })
+    }
+
+    pub fn listen(
+        network: BaseCurrencyNetwork,
+        data: GetDataResponse,
+        addr: &SocketAddr,
+    ) -> io::Result<Addr<Self>> {
+        let tcp = TcpListener::bind(addr)?;
+        Ok(Self::start(
+            network,
+            data,
+            tcp.incoming().map(|tcp| Box::new(tcp) as Box<dyn Duplex>),
+        ))
+    }
+
+    fn dispatcher(addr: Addr<Self>) -> impl SendableDispatcher {
+        chain(ActorDispatcher::<Self, Ping>::new(addr.clone()))
+            .forward_to(ActorDispatcher::<Self, PreliminaryGetDataRequest>::new(
+                addr.clone(),
+            ))
+            .forward_to(ActorDispatcher::<Self, GetUpdatedDataRequest>::new(
+                addr.clone(),
+            ))
+            .forward_to(ActorDispatcher::<Self, GetPeersRequest>::new(addr.clone()))
+            .forward_to(ActorDispatcher::<Self, CloseConnectionMessage>::new(addr))
+    }
+
+    fn reply<M: Into<network_envelope::Message> + 'static>(&self, id: ConnectionId, msg: M) {
+        if let Some(conn) = self.connections.get(&id) {
+            arbiter_spawn!(conn.send(Payload(msg)));
+        }
+    }
+
+    // Keeps the recorded order so every run serves the same bytes
+    fn data_response(
+        &self,
+        request_nonce: i32,
+        excluded_keys: Vec<Vec<u8>>,
+        is_get_updated_data_response: bool,
+    ) -> GetDataResponse {
+        let excluded: HashSet<Vec<u8>> = excluded_keys.into_iter().collect();
+        GetDataResponse {
+            request_nonce,
+            is_get_updated_data_response,
+            data_set: self
+                .data
+                .data_set
+                .iter()
+                .filter(|wrapper| {
+                    wrapper
+                        .bisq_hash()
+                        .map_or(true, |hash| !excluded.contains(&Vec::from(hash)))
+                })
+                .cloned()
+                .collect(),
+            supported_capabilities: local_capabilities(true),
+            persistable_network_payload_items: self
+                .data
+                .persistable_network_payload_items
+                .iter()
+                .filter(|payload| {
+                    payload
+                        .bisq_hash()
+                        .map_or(true, |hash| !excluded.contains(&Vec::from(hash)))
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+}
+impl StreamHandler<Box<dyn Duplex>, io::Error> for FixtureSeed {
+    fn handle(&mut self, stream: Box<dyn Duplex>, ctx: &mut Self::Context) {
+        let (id, conn) = Connection::from_stream(
+            stream,
+            self.network.into(),
+            Self::dispatcher(ctx.address()),
+            DEFAULT_MAX_FRAME_SIZE,
+        );
+        info!("Fixture seed accepted {:?}", id);
+        self.connections.insert(id, conn);
+    }
+}
+impl Handler<Receive<Ping>> for FixtureSeed {
+    type Result = ();
+    fn handle(&mut self, Receive(id, ping): Receive<Ping>, _: &mut Self::Context) {
+        self.reply(
+            id,
+            Pong {
+                request_nonce: ping.nonce,
+            },
+        );
+    }
+}
+impl Handler<Receive<PreliminaryGetDataRequest>> for FixtureSeed {
+    type Result = ();
+    fn handle(
+        &mut self,
+        Receive(id, request): Receive<PreliminaryGetDataRequest>,
+        _: &mut Self::Context,
+    ) {
+        let response = self.data_response(request.nonce, request.excluded_keys, false);
+        self.reply(id, response);
+    }
+}
+impl Handler<Receive<GetUpdatedDataRequest>> for FixtureSeed {
+    type Result = ();
+    fn handle(
+        &mut self,
+        Receive(id, request): Receive<GetUpdatedDataRequest>,
+        _: &mut Self::Context,
+    ) {
+        let response = self.data_response(request.nonce, request.excluded_keys, true);
+        self.reply(id, response);
+    }
+}
+impl Handler<Receive<GetPeersRequest>> for FixtureSeed {
+    type Result = ();
+    fn handle(&mut self, Receive(id, request): Receive<GetPeersRequest>, _: &mut Self::Context) {
+        self.reply(
+            id,
+            GetPeersResponse {
+                request_nonce: request.nonce,
+                reported_peers: Vec::new(),
+                supported_capabilities: local_capabilities(true),
+            },
+        );
+    }
+}
+impl Handler<Receive<CloseConnectionMessage>> for FixtureSeed {
+    type Result = ();
+    fn handle(&mut self, Receive(id, _): Receive<CloseConnectionMessage>, _: &mut Self::Context) {
+        self.connections.remove(&id);
+    }
+}
+
+// Serves the data on the regtest seed node ports so a daemon can bootstrap offline
+pub fn serve(data: GetDataResponse, listen_addr: IpAddr) {
+    let sys = System::new("fixture-seed");
+    let network = BaseCurrencyNetwork::BtcRegtest;
+    for seed in seed_nodes(&network) {
+        let addr = SocketAddr::new(listen_addr, seed.port as u16);
+        FixtureSeed::listen(network, data.clone(), &addr).expect("Unable to bind port");
+        info!("Fixture seed listening @ {}", addr);
+    }
+    let _ = sys.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bisq::persistence,
+        p2p::{transport::Memory, Request},
+    };
+    use std::env;
+
+    #[derive(Clone)]
+    struct Ignore;
+    impl Dispatcher for Ignore {
+        fn dispatch(&self, _: ConnectionId, _: network_envelope::Message) -> Dispatch {
+            Dispatch::Consumed
+        }
+    }
+
+    fn trade(hash: u8) -> PersistableNetworkPayload {
+        PersistableNetworkPayload {
+            message: Some(persistable_network_payload::Message::TradeStatistics2(
+                TradeStatistics2 {
+                    hash: vec![hash; 20],
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    fn seed_addr() -> NodeAddress {
+        NodeAddress {
+            host_name: "127.0.0.1".to_string(),
+            port: 2002,
+        }
+    }
+
+    #[test]
+    fn replays_recorded_response() {
+        let path = env::temp_dir().join(format!("risq-fixture-{}", gen_nonce()));
+        let recorded = GetDataResponse {
+            persistable_network_payload_items: vec![trade(1), trade(2)],
+            ..Default::default()
+        };
+        persistence::store(&path, &recorded).unwrap();
+        let data: GetDataResponse = persistence::load(&path).expect("Couldn't load fixture");
+
+        let mut sys = System::new("fixture");
+        let memory = Memory::new();
+        let incoming = memory
+            .listen(seed_addr())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe));
+        FixtureSeed::start(BaseCurrencyNetwork::BtcRegtest, data, incoming);
+        let (_, conn) = sys
+            .block_on(Connection::open(
+                seed_addr(),
+                BaseCurrencyNetwork::BtcRegtest.into(),
+                Ignore,
+                &memory,
+                DEFAULT_MAX_FRAME_SIZE,
+            ))
+            .unwrap();
+
+        let pong = sys
+            .block_on(
+                conn.send(Request(Ping {
+                    nonce: 3,
+                    last_round_trip_time: 0,
+                }))
+                .flatten(),
+            )
+            .unwrap();
+        assert_eq!(pong.request_nonce, 3);
+
+        let preliminary = sys
+            .block_on(
+                conn.send(Request(PreliminaryGetDataRequest {
+                    nonce: 4,
+                    excluded_keys: Vec::new(),
+                    supported_capabilities: Vec::new(),
+                }))
+                .flatten(),
+            )
+            .unwrap();
+        assert_eq!(preliminary.request_nonce, 4);
+        assert!(!preliminary.is_get_updated_data_response);
+        assert_eq!(
+            preliminary.persistable_network_payload_items,
+            recorded.persistable_network_payload_items
+        );
+
+        let updated = sys
+            .block_on(
+                conn.send(Request(GetUpdatedDataRequest {
+                    sender_node_address: None,
+                    nonce: 5,
+                    excluded_keys: vec![Vec::from(trade(1).bisq_hash().unwrap())],
+                }))
+                .flatten(),
+            )
+            .unwrap();
+        assert!(updated.is_get_updated_data_response);
+        assert_eq!(updated.persistable_network_payload_items, vec![trade(2)]);
+
+        let peers = sys
+            .block_on(
+                conn.send(Request(GetPeersRequest {
+                    nonce: 6,
+                    ..Default::default()
+                }))
+                .flatten(),
+            )
+            .unwrap();
+        assert_eq!(peers.request_nonce, 6);
+        assert!(peers.reported_peers.is_empty());
+    }
+}
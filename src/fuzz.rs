@@ -0,0 +1,103 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`. Each one feeds
+//! arbitrary bytes through code that handles input from peers.
+
+use crate::{
+    bisq::payload::*,
+    daemon,
+    p2p::{MessageStream, DEFAULT_MAX_FRAME_SIZE},
+    prelude::*,
+};
+use prost::Message;
+use std::io::Cursor;
+
+pub fn framing(data: &[u8]) {
+    let mut stream = MessageStream::new(Cursor::new(data), DEFAULT_MAX_FRAME_SIZE);
+    let _ = future::poll_fn(|| loop {
+        match stream.poll() {
+            Ok(Async::Ready(Some(_))) => (),
+            Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+            Ok(Async::NotReady) | Err(_) => return Err(()),
+        }
+    })
+    .wait();
+}
+
+pub fn verification(data: &[u8]) {
+    let message = match NetworkEnvelope::decode(data).map(|envelope| envelope.message) {
+        Ok(Some(message)) => message,
+        _ => return,
+    };
+    match message {
+        network_envelope::Message::AddDataMessage(msg) => {
+            if let Some(entry) = msg
+                .entry
+                .as_ref()
+                .and_then(StorageEntryWrapper::protected_storage_entry)
+            {
+                let _ = entry.verify();
+                let _ = entry.verify_removal(&entry.owner_pub_key_bytes);
+            }
+        }
+        network_envelope::Message::RemoveDataMessage(msg) => {
+            if let Some(entry) = msg.protected_storage_entry {
+                let _ = entry.verify_removal(&entry.owner_pub_key_bytes);
+            }
+        }
+        network_envelope::Message::RemoveMailboxDataMessage(msg) => {
+            if let Some(entry) = msg.protected_storage_entry {
+                let _ = entry.verify_removal();
+            }
+        }
+        // The owner key normally comes from the stored offer, here it is
+        // whatever the input put into the signature
+        network_envelope::Message::RefreshOfferMessage(msg) => {
+            let _ = msg.payload_hash();
+            let _ = msg.verify(&msg.signature, &StoragePayload::default());
+        }
+        network_envelope::Message::AddPersistableNetworkPayloadMessage(msg) => {
+            let _ = msg
+                .payload
+                .as_ref()
+                .and_then(PersistableNetworkPayload::bisq_hash);
+        }
+        network_envelope::Message::GetPeersRequest(msg) => {
+            msg.reported_peers.iter().for_each(|peer| {
+                let _ = peer.reported_alive_at();
+            });
+        }
+        network_envelope::Message::GetPeersResponse(msg) => {
+            msg.reported_peers.iter().for_each(|peer| {
+                let _ = peer.reported_alive_at();
+            });
+        }
+        network_envelope::Message::GetDataResponse(msg) => {
+            msg.data_set.iter().for_each(|wrapper| {
+                let _ = wrapper.bisq_hash();
+            });
+            msg.persistable_network_payload_items
+                .iter()
+                .for_each(|payload| {
+                    let _ = payload.bisq_hash();
+                });
+        }
+        _ => (),
+    }
+}
+
+pub fn conversion(data: &[u8]) {
+    if let Ok(entry) = ProtectedStorageEntry::decode(data) {
+        if let Some(hash) = entry
+            .storage_payload
+            .as_ref()
+            .map(StoragePayload::bisq_hash)
+        {
+            let _ = daemon::open_offer(entry, hash);
+        }
+    }
+    #[cfg(feature = "statistics")]
+    {
+        if let Ok(payload) = PersistableNetworkPayload::decode(data) {
+            let _ = daemon::trade_statistics2(payload);
+        }
+    }
+}
@@ -11,6 +11,9 @@ mod checker;
 mod daemon;
 mod domain;
 mod error;
+mod fixture;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 mod p2p;
 
 pub mod cli;
@@ -1,66 +1,66 @@
 use super::{
-    connection::{Connection, ConnectionId, Request},
+    connection::{transport::Transport, Connection, ConnectionId, Request},
     dispatch::SendableDispatcher,
     peers::{Peers, SeedConnection},
     server::event::ServerStarted,
 };
 use crate::{
     bisq::{
-        constants::{seed_nodes, BaseCurrencyNetwork, LOCAL_CAPABILITIES},
+        constants::{local_capabilities, seed_nodes, BaseCurrencyNetwork},
         payload::*,
+        PersistentMessageHash, SequencedMessageHash,
     },
     error::Error,
-    prelude::{sync::oneshot, *},
+    prelude::{future::Shared, sync::oneshot, *},
 };
 use rand::{seq::SliceRandom, thread_rng};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 pub struct Bootstrap<D: SendableDispatcher> {
     network: BaseCurrencyNetwork,
-    proxy_port: Option<u16>,
+    transport: Arc<dyn Transport>,
+    max_frame_size: usize,
     addr_notify: Option<oneshot::Sender<NodeAddress>>,
-    addr_rec: Option<oneshot::Receiver<NodeAddress>>,
+    local_addr: Shared<oneshot::Receiver<NodeAddress>>,
     seed_nodes: Vec<NodeAddress>,
+    parallel_seeds: usize,
+    seed_node: bool,
+    backoff: Duration,
     peers: Addr<Peers<D>>,
     dispatcher: D,
 }
 impl<D: SendableDispatcher> Actor for Bootstrap<D> {
     type Context = Context<Bootstrap<D>>;
     fn started(&mut self, ctx: &mut Self::Context) {
-        let addr = self.seed_nodes.pop().expect("No seed nodes defined");
-        ctx.spawn(
-            fut::wrap_future(bootstrap_from_seed(
-                addr.clone(),
-                self.addr_rec.take().expect("Receiver already removed"),
-                self.network,
-                self.dispatcher.clone(),
-                self.proxy_port,
-            ))
-            .map_err(|_, _, _| ())
-            .and_then(move |seed_result, bootstrap: &mut Bootstrap<D>, _ctx| {
-                fut::wrap_future(
-                    bootstrap
-                        .peers
-                        .send(SeedConnection(
-                            addr,
-                            seed_result.connection_id,
-                            seed_result.connection,
-                        ))
-                        .map_err(|_| ()),
-                )
-            })
-            .map(|_, _, ctx| ctx.stop()),
-        );
+        // A seed node has to know its own address before it can pick the other seeds
+        if !self.seed_node {
+            self.bootstrap_round(ctx);
+        }
     }
 }
 impl<D: SendableDispatcher> Handler<ServerStarted> for Bootstrap<D> {
     type Result = ();
-    fn handle(&mut self, ServerStarted(local_addr): ServerStarted, _ctx: &mut Self::Context) {
+    fn handle(&mut self, ServerStarted(local_addr): ServerStarted, ctx: &mut Self::Context) {
+        if self.seed_node {
+            self.seed_nodes.retain(|addr| addr != &local_addr);
+        }
         self.addr_notify
             .take()
             .expect("Local addr notifier already used")
             .send(local_addr)
             .map_err(|e| error!("ERR: {:?}", e))
             .expect("Couldn't send local address");
+        if self.seed_node {
+            if self.seed_nodes.is_empty() {
+                info!("No other seed nodes to bootstrap from");
+                ctx.stop();
+            } else {
+                self.bootstrap_round(ctx);
+            }
+        }
     }
 }
 impl<D: SendableDispatcher> Bootstrap<D> {
@@ -68,7 +68,10 @@ impl<D: SendableDispatcher> Bootstrap<D> {
         network: BaseCurrencyNetwork,
         peers: Addr<Peers<D>>,
         dispatcher: D,
-        proxy_port: Option<u16>,
+        transport: Arc<dyn Transport>,
+        max_frame_size: usize,
+        parallel_seeds: usize,
+        seed_node: bool,
     ) -> Addr<Bootstrap<D>> {
         let mut seed_nodes = seed_nodes(&network);
         seed_nodes.shuffle(&mut thread_rng());
@@ -76,113 +79,370 @@ impl<D: SendableDispatcher> Bootstrap<D> {
         Self {
             network,
             addr_notify: Some(addr_notify),
-            addr_rec: Some(addr_rec),
-            proxy_port,
+            local_addr: addr_rec.shared(),
+            transport,
+            max_frame_size,
             seed_nodes,
+            parallel_seeds: usize::max(parallel_seeds, 1),
+            seed_node,
+            backoff: INITIAL_BACKOFF,
             peers,
             dispatcher,
         }
         .start()
     }
+
+    fn bootstrap_round(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let split_at = self.seed_nodes.len().saturating_sub(self.parallel_seeds);
+        let seeds = self.seed_nodes.split_off(split_at);
+        if seeds.is_empty() {
+            self.fail(ctx);
+            return;
+        }
+        let attempts: Vec<_> = seeds
+            .into_iter()
+            .map(|seed_addr| {
+                bootstrap_from_seed(
+                    seed_addr.clone(),
+                    self.local_addr
+                        .clone()
+                        .map(|addr| (*addr).clone())
+                        .map_err(|_| Error::ReceiveOneshotError),
+                    self.network,
+                    self.dispatcher.clone(),
+                    &*self.transport,
+                    self.max_frame_size,
+                    local_capabilities(self.seed_node),
+                )
+                .then(move |result| {
+                    Ok::<_, ()>(
+                        result
+                            .map_err(|e| match e {
+                                Error::RequestTimeout | Error::ConnectTimeout => {
+                                    warn!("Bootstrap from {:?} timed out", seed_addr)
+                                }
+                                e => warn!("Bootstrap from {:?} failed: {:?}", seed_addr, e),
+                            })
+                            .ok(),
+                    )
+                })
+            })
+            .collect();
+        ctx.spawn(fut::wrap_future(future::join_all(attempts)).map(
+            |results, bootstrap: &mut Self, ctx| {
+                let results: Vec<SeedResult> = results.into_iter().filter_map(|r| r).collect();
+                if results.is_empty() {
+                    bootstrap.retry(ctx)
+                } else {
+                    bootstrap.complete(results, ctx)
+                }
+            },
+        ));
+    }
+
+    fn retry(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if self.seed_nodes.is_empty() {
+            self.fail(ctx);
+            return;
+        }
+        info!("Retrying bootstrap with next seed in {:?}", self.backoff);
+        ctx.run_later(self.backoff, |bootstrap, ctx| {
+            bootstrap.bootstrap_round(ctx)
+        });
+        self.backoff = Duration::min(self.backoff * 2, MAX_BACKOFF);
+    }
+
+    fn fail(&mut self, ctx: &mut <Self as Actor>::Context) {
+        // Some seed node has to be the first one up so it keeps serving without data
+        if self.seed_node {
+            warn!("None of the other seed nodes could be reached");
+            ctx.stop();
+            return;
+        }
+        error!("Bootstrap failed: none of the seed nodes could be reached");
+        System::current().stop_with_code(1);
+    }
+
+    fn complete(&mut self, results: Vec<SeedResult>, ctx: &mut <Self as Actor>::Context) {
+        let mut responses = Vec::new();
+        let mut seed_connections = Vec::with_capacity(results.len());
+        for SeedResult {
+            seed_addr,
+            connection_id,
+            connection,
+            data,
+        } in results
+        {
+            responses.extend(data.into_iter().map(|response| (connection_id, response)));
+            seed_connections.push(self.peers.send(SeedConnection(
+                seed_addr,
+                connection_id,
+                connection,
+            )));
+        }
+        let merged = merge_responses(responses);
+        info!(
+            "Bootstrapped {} items from {} seed(s)",
+            merged
+                .values()
+                .map(|data| data.data_set.len() + data.persistable_network_payload_items.len())
+                .sum::<usize>(),
+            seed_connections.len()
+        );
+        // Each entry is dispatched once, charged to the seed whose copy was kept
+        for (origin, data) in merged {
+            self.dispatcher.dispatch(origin, data.into());
+        }
+        ctx.spawn(
+            fut::wrap_future(future::join_all(seed_connections))
+                .then(|_, _, ctx: &mut <Self as Actor>::Context| fut::ok(ctx.stop())),
+        );
+    }
 }
 struct SeedResult {
+    seed_addr: NodeAddress,
     connection: Addr<Connection>,
     connection_id: ConnectionId,
+    data: Vec<GetDataResponse>,
 }
 fn bootstrap_from_seed<D: SendableDispatcher>(
     seed_addr: NodeAddress,
-    local_addr: oneshot::Receiver<NodeAddress>,
+    local_addr: impl Future<Item = NodeAddress, Error = Error>,
     network: BaseCurrencyNetwork,
     dispatcher: D,
-    proxy_port: Option<u16>,
+    transport: &dyn Transport,
+    max_frame_size: usize,
+    capabilities: Vec<i32>,
 ) -> impl Future<Item = SeedResult, Error = Error> {
     let preliminary_get_data_request = PreliminaryGetDataRequest {
         nonce: gen_nonce(),
         excluded_keys: Vec::new(),
-        supported_capabilities: LOCAL_CAPABILITIES.clone(),
+        supported_capabilities: capabilities,
     };
     info!("Bootstrapping from seed: {:?}", seed_addr);
-    Connection::open(seed_addr, network.into(), dispatcher.clone(), proxy_port)
-        .and_then(|(id, conn)| {
-            debug!("Sending PreliminaryGetDataRequest to seed.");
-            conn.send(Request(preliminary_get_data_request))
-                .flatten()
-                .map(move |response| (id, conn, response))
-        })
-        .and_then(move |(id, conn, preliminary_data_response)| {
-            debug!(
-                "Preliminary data response has {} items",
-                preliminary_data_response.data_set.len()
-                    + preliminary_data_response
-                        .persistable_network_payload_items
-                        .len()
-            );
-            let excluded_keys = get_excluded_keys(&preliminary_data_response);
-            dispatcher.dispatch(id, preliminary_data_response.into());
-
-            local_addr
-                .map(move |addr| {
-                    (
-                        GetUpdatedDataRequest {
-                            sender_node_address: addr.into(),
-                            nonce: gen_nonce(),
-                            excluded_keys,
-                        },
-                        id,
-                        conn,
-                        dispatcher,
-                    )
-                })
-                .map_err(|e| e.into())
-        })
-        .and_then(|(request, id, conn, dispatcher)| {
-            debug!("Sending GetUpdatedDataRequest to seed.");
-            conn.send(Request(request))
-                .flatten()
-                .map(move |get_updated_data_response| {
-                    debug!(
-                        "Update data response has {} items",
-                        get_updated_data_response.data_set.len()
-                            + get_updated_data_response
-                                .persistable_network_payload_items
-                                .len()
-                    );
-                    dispatcher.dispatch(id, get_updated_data_response.into());
-                    SeedResult {
-                        connection_id: id,
-                        connection: conn,
-                    }
-                })
+    Connection::open(
+        seed_addr.clone(),
+        network.into(),
+        dispatcher,
+        transport,
+        max_frame_size,
+    )
+    .and_then(|(id, conn)| {
+        debug!("Sending PreliminaryGetDataRequest to seed.");
+        conn.send(Request(preliminary_get_data_request))
+            .flatten()
+            .map(move |response| (id, conn, response))
+    })
+    .and_then(move |(id, conn, preliminary_data_response)| {
+        debug!(
+            "Preliminary data response has {} items",
+            preliminary_data_response.data_set.len()
+                + preliminary_data_response
+                    .persistable_network_payload_items
+                    .len()
+        );
+        let excluded_keys = get_excluded_keys(&preliminary_data_response);
+        local_addr.map(move |addr| {
+            (
+                GetUpdatedDataRequest {
+                    sender_node_address: addr.into(),
+                    nonce: gen_nonce(),
+                    excluded_keys,
+                },
+                id,
+                conn,
+                preliminary_data_response,
+            )
         })
+    })
+    .and_then(|(request, id, conn, preliminary_data_response)| {
+        debug!("Sending GetUpdatedDataRequest to seed.");
+        conn.send(Request(request))
+            .flatten()
+            .map(move |get_updated_data_response| {
+                debug!(
+                    "Update data response has {} items",
+                    get_updated_data_response.data_set.len()
+                        + get_updated_data_response
+                            .persistable_network_payload_items
+                            .len()
+                );
+                SeedResult {
+                    seed_addr,
+                    connection_id: id,
+                    connection: conn,
+                    data: vec![preliminary_data_response, get_updated_data_response],
+                }
+            })
+    })
 }
 fn get_excluded_keys(preliminary_data_response: &GetDataResponse) -> Vec<Vec<u8>> {
     preliminary_data_response
         .data_set
         .iter()
-        .map(|w| w.message.as_ref().expect("Couldn't unwrap message"))
-        .map(|m| match m {
-            storage_entry_wrapper::Message::ProtectedStorageEntry(entry) => entry,
-            storage_entry_wrapper::Message::ProtectedMailboxStorageEntry(mailbox_entry) => {
-                mailbox_entry
-                    .entry
-                    .as_ref()
-                    .expect("Couldn't unwrap StorageEntry")
-            }
-        })
-        .map(|entry| {
-            entry
-                .storage_payload
-                .as_ref()
-                .expect("Couldn't unwrap storage_payload")
-                .bisq_hash()
-                .into()
-        })
+        .filter_map(StorageEntryWrapper::bisq_hash)
+        .map(Vec::<u8>::from)
         .chain(
             preliminary_data_response
                 .persistable_network_payload_items
                 .iter()
-                .map(PersistableNetworkPayload::bisq_hash)
+                .filter_map(PersistableNetworkPayload::bisq_hash)
                 .map(Vec::<u8>::from),
         )
         .collect()
 }
+fn merge_responses(
+    responses: Vec<(ConnectionId, GetDataResponse)>,
+) -> HashMap<ConnectionId, GetDataResponse> {
+    let sequence = |w: &StorageEntryWrapper| w.protected_storage_entry().map(|e| e.sequence_number);
+    let mut data_set: HashMap<SequencedMessageHash, (ConnectionId, StorageEntryWrapper)> =
+        HashMap::new();
+    let mut payloads: HashMap<PersistentMessageHash, (ConnectionId, PersistableNetworkPayload)> =
+        HashMap::new();
+    for (origin, response) in responses {
+        for wrapper in response.data_set {
+            if let Some(hash) = wrapper.bisq_hash() {
+                let is_newer = data_set.get(&hash).map_or(true, |(_, existing)| {
+                    sequence(existing) < sequence(&wrapper)
+                });
+                if is_newer {
+                    data_set.insert(hash, (origin, wrapper));
+                }
+            }
+        }
+        for payload in response.persistable_network_payload_items {
+            if let Some(hash) = payload.bisq_hash() {
+                payloads.entry(hash).or_insert((origin, payload));
+            }
+        }
+    }
+    let mut merged: HashMap<ConnectionId, GetDataResponse> = HashMap::new();
+    for (_, (origin, wrapper)) in data_set {
+        merged.entry(origin).or_default().data_set.push(wrapper);
+    }
+    for (_, (origin, payload)) in payloads {
+        merged
+            .entry(origin)
+            .or_default()
+            .persistable_network_payload_items
+            .push(payload);
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::FixtureSeed,
+        p2p::{
+            dispatch::{Dispatch, Dispatcher},
+            message::GetNetworkInfo,
+            transport::Memory,
+            Broadcaster, BundleConfig, PeersConfig, DEFAULT_MAX_FRAME_SIZE,
+        },
+    };
+    use std::{
+        env, fs, io,
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+    use tokio::timer::Delay;
+
+    // Counts the payloads bootstrapped from each seed connection
+    #[derive(Clone, Default)]
+    struct Bootstrapped(Arc<Mutex<HashMap<ConnectionId, usize>>>);
+    impl Dispatcher for Bootstrapped {
+        fn dispatch(&self, conn: ConnectionId, msg: network_envelope::Message) -> Dispatch {
+            if let network_envelope::Message::GetDataResponse(response) = msg {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .insert(conn, response.persistable_network_payload_items.len());
+            }
+            Dispatch::Consumed
+        }
+    }
+
+    fn trade(hash: u8) -> PersistableNetworkPayload {
+        PersistableNetworkPayload {
+            message: Some(persistable_network_payload::Message::TradeStatistics2(
+                TradeStatistics2 {
+                    hash: vec![hash; 20],
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    #[test]
+    fn bootstraps_from_seeds_over_memory_transport() {
+        let mut sys = System::new("bootstrap");
+        let network = BaseCurrencyNetwork::BtcRegtest;
+        let memory = Memory::new();
+        let data = GetDataResponse {
+            persistable_network_payload_items: vec![trade(1), trade(2)],
+            ..Default::default()
+        };
+        for seed in seed_nodes(&network) {
+            let incoming = memory
+                .listen(seed)
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe));
+            FixtureSeed::start(network, data.clone(), incoming);
+        }
+        let home = env::temp_dir().join(format!("risq-bootstrap-{}", gen_nonce()));
+        fs::create_dir_all(&home).unwrap();
+        let bootstrapped = Bootstrapped::default();
+        let peers = Peers::start(
+            network,
+            Broadcaster::start(),
+            bootstrapped.clone(),
+            Arc::new(memory.clone()),
+            PeersConfig {
+                peers_file: home.join("peers"),
+                max_peer_age: Duration::from_secs(60 * 60),
+                bans_file: home.join("bans"),
+                ban_period: Duration::from_secs(60 * 60),
+                max_failed_pings: 3,
+                bundling: BundleConfig {
+                    window: Duration::from_millis(50),
+                    max_size: 100,
+                },
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+                seed_node: false,
+            },
+        );
+        let bootstrap = Bootstrap::start(
+            network,
+            peers.clone(),
+            bootstrapped.clone(),
+            Arc::new(memory),
+            DEFAULT_MAX_FRAME_SIZE,
+            2,
+            false,
+        );
+        bootstrap.do_send(ServerStarted(NodeAddress {
+            host_name: "127.0.0.1".to_string(),
+            port: 4002,
+        }));
+
+        // Bootstrap stops once it has handed the seed connections to Peers
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while bootstrap.connected() {
+            assert!(Instant::now() < deadline, "Bootstrap didn't complete");
+            sys.block_on(Delay::new(Instant::now() + Duration::from_millis(10)))
+                .unwrap();
+        }
+        let counts = bootstrapped.0.lock().unwrap().clone();
+        // Both seeds serve the same payloads, which are only dispatched once
+        assert_eq!(counts.values().sum::<usize>(), 2);
+        let mut seeds = sys
+            .block_on(peers.send(GetNetworkInfo))
+            .unwrap()
+            .unwrap()
+            .bootstrap_seeds;
+        seeds.sort_by_key(|seed| seed.port);
+        assert_eq!(seeds, seed_nodes(&network));
+        fs::remove_dir_all(home).unwrap();
+    }
+}
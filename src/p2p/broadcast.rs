@@ -1,8 +1,11 @@
 use super::{
-    connection::{Connection, ConnectionId, Payload},
+    connection::{Connection, ConnectionId, Payload, Shutdown},
     peers::event::ConnectionAdded,
 };
-use crate::{bisq::payload::network_envelope, prelude::*};
+use crate::{
+    bisq::{constants::CloseConnectionReason, payload::network_envelope},
+    prelude::*,
+};
 use std::collections::HashMap;
 
 pub struct Broadcaster {
@@ -44,6 +47,44 @@ where
         });
     }
 }
+pub struct SendTo<M: Into<network_envelope::Message>>(pub ConnectionId, pub M);
+impl<M> Message for SendTo<M>
+where
+    M: Into<network_envelope::Message>,
+{
+    type Result = ();
+}
+impl<M: 'static> Handler<SendTo<M>> for Broadcaster
+where
+    M: Into<network_envelope::Message> + Send,
+{
+    type Result = ();
+    fn handle(&mut self, SendTo(id, message): SendTo<M>, _ctx: &mut Self::Context) {
+        match self.connections.get(&id).and_then(WeakAddr::upgrade) {
+            Some(conn) => arbiter_spawn!(conn.send(Payload(message))),
+            None => {
+                self.connections.remove(&id);
+            }
+        }
+    }
+}
+pub struct CloseAll(pub CloseConnectionReason);
+impl Message for CloseAll {
+    type Result = Result<(), ()>;
+}
+impl Handler<CloseAll> for Broadcaster {
+    type Result = ResponseFuture<(), ()>;
+    fn handle(&mut self, CloseAll(reason): CloseAll, _ctx: &mut Self::Context) -> Self::Result {
+        let closing: Vec<_> = self
+            .connections
+            .drain()
+            .filter_map(|(_, conn)| conn.upgrade())
+            .map(|conn| conn.send(Shutdown(reason)).then(|_| Ok::<(), ()>(())))
+            .collect();
+        info!("Closing {} connections", closing.len());
+        Box::new(future::join_all(closing).map(|_| ()))
+    }
+}
 impl Handler<ConnectionAdded> for Broadcaster {
     type Result = ();
     fn handle(
@@ -1,24 +1,60 @@
+mod codec;
 mod message_stream;
+mod socks;
+mod stats;
 
-use super::dispatch::{Dispatch, Dispatcher, SendableDispatcher};
+pub mod transport;
+
+use super::{
+    dispatch::{Dispatch, Dispatcher, SendableDispatcher},
+    reputation::{Misbehaviour, Offence, ReportMisbehaviourTo},
+};
 use crate::{
     bisq::{constants::CloseConnectionReason, correlation::*, payload::*},
     error,
     prelude::{
         future::Either,
-        io::{flush, write_all},
-        net::TcpStream,
-        reactor::Handle,
+        io::{AsyncRead, AsyncWrite},
         sync::{mpsc, oneshot},
         *,
     },
 };
-use message_stream::MessageStream;
-use prost::{encoding::encoded_len_varint, Message};
-use socks::Socks5Stream;
-use std::{collections::HashMap, net::ToSocketAddrs, thread};
+use codec::EnvelopeCodec;
+pub(crate) use message_stream::MessageStream;
+use prost::{encoding::encoded_len_varint, Message as _};
+use stats::{Counted, Traffic};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    convert::TryFrom,
+    fmt, mem,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use tokio::{codec::FramedWrite, timer::Delay};
+use transport::Transport;
 use uuid::Uuid;
 
+pub use socks::SocksError;
+pub use stats::ConnectionStats;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(90);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REQUEST_TIMEOUTS: u8 = 3;
+// How long a reply to a timed out request is still accepted as merely late
+const LATE_RESPONSE_WINDOW: Duration = Duration::from_secs(5 * 60);
+const FLOOD_WINDOW: Duration = Duration::from_secs(10);
+const FLOOD_THRESHOLD: u32 = 1000;
+// Upper bound for the message version and field headers of a bundle envelope
+const BUNDLE_OVERHEAD: usize = 32;
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BundleConfig {
+    pub window: Duration,
+    pub max_size: usize,
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct ConnectionId(Uuid);
 impl ConnectionId {
@@ -26,25 +62,98 @@ impl ConnectionId {
         ConnectionId(Uuid::new_v4())
     }
 }
+impl From<ConnectionId> for Vec<u8> {
+    fn from(id: ConnectionId) -> Vec<u8> {
+        id.0.as_bytes().to_vec()
+    }
+}
+impl TryFrom<&[u8]> for ConnectionId {
+    type Error = ();
+    fn try_from(bytes: &[u8]) -> Result<ConnectionId, Self::Error> {
+        Uuid::from_slice(bytes).map(ConnectionId).map_err(|_| ())
+    }
+}
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 pub struct Connection {
     id: ConnectionId,
     writer: mpsc::Sender<network_envelope::Message>,
+    bundling: Rc<Cell<Option<BundleConfig>>>,
     dispatcher: Box<dyn Dispatcher>,
     response_channels: HashMap<CorrelationId, oneshot::Sender<network_envelope::Message>>,
+    request_timeouts: u8,
+    timed_out_requests: HashMap<CorrelationId, Instant>,
+    misbehaviour: Option<Recipient<Misbehaviour>>,
+    flood_window_start: Instant,
+    received_in_flood_window: u32,
+    traffic: Traffic,
 }
 impl Actor for Connection {
     type Context = Context<Connection>;
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        let stats = self.traffic.stats();
+        info!(
+            "{:?} stopped after {:?}: received {} bytes in {} messages, sent {} bytes in {} messages",
+            self.id,
+            stats.age,
+            stats.bytes_received,
+            stats.messages_received.values().sum::<u64>(),
+            stats.bytes_sent,
+            stats.messages_sent.values().sum::<u64>(),
+        );
+    }
 }
 impl StreamHandler<network_envelope::Message, error::Error> for Connection {
-    fn handle(&mut self, msg: network_envelope::Message, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: network_envelope::Message, ctx: &mut Self::Context) {
+        self.check_flooding();
+        self.traffic.received(&msg);
+        self.dispatcher.received(self.id, &msg);
         if let Some(id) = Option::<CorrelationId>::from(&msg) {
             if let Some(channel) = self.response_channels.remove(&id) {
-                channel.send(msg).expect("Couldn't send response");
+                if channel.send(msg).is_err() {
+                    debug!("{:?} received response after request was dropped", self.id);
+                }
+                return;
+            }
+            if self.timed_out_requests.remove(&id).is_some() {
+                debug!("{:?} received response after request timed out", self.id);
+                return;
+            }
+            if is_response(&msg) {
+                warn!("{:?} received unsolicited response", self.id);
+                self.report(Offence::UnsolicitedResponse);
                 return;
             }
         }
-        if let Dispatch::Retained(msg) = self.dispatcher.dispatch(self.id, msg) {
-            warn!("{:?} retained message: {:?}", self.id, msg)
+        match self.dispatcher.dispatch(self.id, msg) {
+            Dispatch::Consumed => (),
+            Dispatch::Retained(msg) => {
+                warn!("{:?} retained message: {:?}", self.id, msg);
+                self.traffic.retained();
+            }
+            Dispatch::Dropped => self.traffic.dropped(),
+            Dispatch::Abusive => {
+                warn!("{:?} keeps exceeding rate limits", self.id);
+                self.traffic.dropped();
+                self.report(Offence::Flooding);
+                ctx.notify(Shutdown(CloseConnectionReason::RuleViolation));
+            }
+        }
+    }
+
+    fn error(&mut self, err: error::Error, ctx: &mut Self::Context) -> Running {
+        warn!("{:?} stream error: {:?}", self.id, err);
+        match err {
+            error::Error::Decode(_) | error::Error::FrameTooLarge(_) => {
+                self.report(Offence::CorruptedData);
+                ctx.notify(Shutdown(CloseConnectionReason::CorruptedData));
+                Running::Continue
+            }
+            _ => Running::Stop,
         }
     }
 
@@ -59,96 +168,238 @@ impl Connection {
         addr: NodeAddress,
         message_version: MessageVersion,
         dispatcher: D,
-        proxy_port: Option<u16>,
+        transport: &dyn Transport,
+        max_frame_size: usize,
     ) -> impl Future<Item = (ConnectionId, Addr<Connection>), Error = error::Error> {
-        match proxy_port {
-            None => Either::A(
-                TcpStream::connect(
-                    &(addr.host_name.as_str(), addr.port as u16)
-                        .to_socket_addrs()
-                        .unwrap()
-                        .next()
-                        .unwrap(),
-                )
-                .map(move |tcp| Connection::from_tcp_stream(tcp, message_version, dispatcher))
-                .map_err(|err| err.into()),
-            ),
-            Some(proxy_port) => {
-                let (send, receive) = oneshot::channel::<Result<Socks5Stream, error::Error>>();
-                thread::spawn(move || {
-                    send.send(
-                        Socks5Stream::connect(
-                            ("127.0.0.1", proxy_port),
-                            (addr.host_name.as_str(), addr.port as u16),
-                        )
-                        .map_err(|e| e.into()),
-                    )
-                    .expect("Couldn't send Socks5Stream");
-                });
-                Either::B(
-                    receive
-                        .map_err(|e| error::Error::from(e))
-                        .flatten()
-                        .and_then(|stream| {
-                            TcpStream::from_std(stream.into_inner(), &Handle::default())
-                                .map_err(|e| e.into())
-                        })
-                        .map(move |tcp| {
-                            Connection::from_tcp_stream(tcp, message_version, dispatcher)
-                        }),
-                )
-            }
-        }
+        transport
+            .connect(&addr)
+            .timeout(CONNECT_TIMEOUT)
+            .map_err(|e| e.into_inner().unwrap_or(error::Error::ConnectTimeout))
+            .map(move |stream| {
+                Connection::from_stream(stream, message_version, dispatcher, max_frame_size)
+            })
     }
-    pub fn from_tcp_stream<D: SendableDispatcher>(
-        connection: TcpStream,
+    pub fn from_stream<S: AsyncRead + AsyncWrite + 'static, D: SendableDispatcher>(
+        connection: S,
         message_version: MessageVersion,
         dispatcher: D,
+        max_frame_size: usize,
     ) -> (ConnectionId, Addr<Connection>) {
+        let bytes_received = Rc::new(Cell::new(0));
+        let bytes_sent = Rc::new(Cell::new(0));
+        let traffic = Traffic::new(bytes_received.clone(), bytes_sent.clone());
         let (reader, writer) = connection.split();
+        let reader = Counted::new(reader, bytes_received);
+        let writer = FramedWrite::new(
+            Counted::new(writer, bytes_sent),
+            EnvelopeCodec::new(max_frame_size),
+        );
         let (send, rec) = mpsc::channel(10);
         let id = ConnectionId::new();
-        arbiter_spawn!(future::loop_fn((rec, writer), move |(rec, writer)| {
+        let bundling = Rc::new(Cell::new(None));
+        let writer_bundling = bundling.clone();
+        let write_loop = future::loop_fn((rec, writer), move |(rec, writer)| {
+            let bundling = writer_bundling.clone();
             rec.into_future()
                 .map_err(|(e, _)| e.into())
                 .and_then(|(msg, rec)| {
                     msg.ok_or(error::Error::ReceiveMPSCError)
                         .map(|msg| (msg, rec))
                 })
-                .and_then(move |(msg, rec)| {
-                    debug!("Sending message {:?}", msg);
-                    let envelope = NetworkEnvelope {
-                        message_version: message_version.into(),
-                        message: Some(msg),
-                    };
-                    let len = envelope.encoded_len();
-                    let required = len + encoded_len_varint(len as u64);
-                    let mut serialized = Vec::with_capacity(required);
-                    envelope
-                        .encode_length_delimited(&mut serialized)
-                        .expect("Could not encode message");
-                    write_all(writer, serialized)
-                        .and_then(|(writer, _)| flush(writer))
-                        .then(|writer| match writer {
-                            Ok(writer) => Ok(Loop::Continue((rec, writer))),
+                .and_then(move |(msg, rec)| match bundling.get() {
+                    Some(config) => Either::A(collect_bundle(msg, rec, config)),
+                    None => Either::B(future::ok((vec![msg], rec))),
+                })
+                .and_then(move |(msgs, rec)| {
+                    let envelopes = into_envelopes(msgs, message_version, max_frame_size);
+                    envelopes
+                        .iter()
+                        .for_each(|envelope| debug!("Sending message {:?}", envelope.message));
+                    writer
+                        .send_all(stream::iter_ok::<_, error::Error>(envelopes))
+                        .then(|result| match result {
+                            Ok((writer, _)) => Ok(Loop::Continue((rec, writer))),
                             Err(e) => Ok(Loop::Break(e)),
                         })
                 })
-                .map_err(|_| ())
-        }));
+        });
         (
             id,
             Connection::create(move |ctx| {
-                ctx.add_stream(MessageStream::new(reader));
+                let connection = ctx.address();
+                // The loop only ends with an error once the Connection dropped its sender
+                arbiter_spawn!(write_loop.map(move |e| {
+                    warn!("{:?} couldn't write: {:?}", id, e);
+                    connection.do_send(Shutdown(CloseConnectionReason::SendMsgFailure));
+                }));
+                ctx.add_stream(MessageStream::new(reader, max_frame_size));
                 Connection {
                     id,
                     writer: send,
+                    bundling,
                     dispatcher: Box::new(dispatcher),
                     response_channels: HashMap::new(),
+                    request_timeouts: 0,
+                    timed_out_requests: HashMap::new(),
+                    misbehaviour: None,
+                    flood_window_start: Instant::now(),
+                    received_in_flood_window: 0,
+                    traffic,
                 }
             }),
         )
     }
+
+    fn report(&self, offence: Offence) {
+        if let Some(recipient) = self.misbehaviour.as_ref() {
+            let _ = recipient.do_send(Misbehaviour(self.id, offence));
+        }
+    }
+
+    fn check_flooding(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.flood_window_start) > FLOOD_WINDOW {
+            self.flood_window_start = now;
+            self.received_in_flood_window = 0;
+        }
+        self.received_in_flood_window += 1;
+        if self.received_in_flood_window == FLOOD_THRESHOLD {
+            warn!("{:?} is flooding us with messages", self.id);
+            self.report(Offence::Flooding);
+        }
+    }
+
+    fn request_timed_out(
+        &mut self,
+        correlation_id: &CorrelationId,
+        counts_as_timeout: bool,
+        ctx: &mut Context<Self>,
+    ) {
+        self.response_channels.remove(correlation_id);
+        let now = Instant::now();
+        self.timed_out_requests
+            .retain(|_, timed_out_at| now.duration_since(*timed_out_at) < LATE_RESPONSE_WINDOW);
+        self.timed_out_requests.insert(correlation_id.clone(), now);
+        if !counts_as_timeout {
+            return;
+        }
+        self.request_timeouts += 1;
+        warn!(
+            "{:?} request timed out ({} in a row)",
+            self.id, self.request_timeouts
+        );
+        if self.request_timeouts >= MAX_REQUEST_TIMEOUTS {
+            ctx.notify(Shutdown(CloseConnectionReason::SendMsgTimeout));
+        }
+    }
+}
+
+// Splits the messages into as few envelopes as possible that each fit into a frame
+fn into_envelopes(
+    msgs: Vec<network_envelope::Message>,
+    message_version: MessageVersion,
+    max_frame_size: usize,
+) -> Vec<NetworkEnvelope> {
+    let wrap = |message| NetworkEnvelope {
+        message_version: message_version.into(),
+        message: Some(message),
+    };
+    let finish = |mut bundle: Vec<NetworkEnvelope>| {
+        if bundle.len() == 1 {
+            bundle.pop().expect("Empty bundle")
+        } else {
+            wrap(BundleOfEnvelopes { envelopes: bundle }.into())
+        }
+    };
+    let mut envelopes = Vec::new();
+    let mut bundle = Vec::new();
+    let mut bundle_len = BUNDLE_OVERHEAD;
+    for msg in msgs {
+        let name = msg.name();
+        let envelope = wrap(msg);
+        let len = envelope.encoded_len();
+        if len > max_frame_size {
+            warn!(
+                "Dropping {} of {} bytes, it exceeds the frame size",
+                name, len
+            );
+            continue;
+        }
+        let bundled_len = 1 + encoded_len_varint(len as u64) + len;
+        if !bundle.is_empty() && bundle_len + bundled_len > max_frame_size {
+            envelopes.push(finish(mem::replace(&mut bundle, Vec::new())));
+            bundle_len = BUNDLE_OVERHEAD;
+        }
+        bundle_len += bundled_len;
+        bundle.push(envelope);
+    }
+    if !bundle.is_empty() {
+        envelopes.push(finish(bundle));
+    }
+    envelopes
+}
+
+// Only waits for the batching window if there is already more than one message queued
+// so that a lone message isn't held back.
+fn collect_bundle(
+    first: network_envelope::Message,
+    rec: mpsc::Receiver<network_envelope::Message>,
+    config: BundleConfig,
+) -> impl Future<
+    Item = (
+        Vec<network_envelope::Message>,
+        mpsc::Receiver<network_envelope::Message>,
+    ),
+    Error = error::Error,
+> {
+    take_queued(rec, vec![first], config.max_size).and_then(move |(msgs, rec)| {
+        if msgs.len() == 1 || msgs.len() >= config.max_size {
+            Either::A(future::ok((msgs, rec)))
+        } else {
+            Either::B(
+                Delay::new(Instant::now() + config.window)
+                    .then(move |_| take_queued(rec, msgs, config.max_size)),
+            )
+        }
+    })
+}
+
+fn take_queued(
+    rec: mpsc::Receiver<network_envelope::Message>,
+    msgs: Vec<network_envelope::Message>,
+    max_size: usize,
+) -> impl Future<
+    Item = (
+        Vec<network_envelope::Message>,
+        mpsc::Receiver<network_envelope::Message>,
+    ),
+    Error = error::Error,
+> {
+    let mut state = Some((msgs, rec));
+    future::poll_fn(move || {
+        let (mut msgs, mut rec) = state.take().expect("Polled after completion");
+        while msgs.len() < max_size {
+            match rec.poll()? {
+                Async::Ready(Some(msg)) => msgs.push(msg),
+                _ => break,
+            }
+        }
+        Ok(Async::Ready((msgs, rec)))
+    })
+}
+
+pub struct EnableBundling(pub BundleConfig);
+impl actix::Message for EnableBundling {
+    type Result = ();
+}
+impl Handler<EnableBundling> for Connection {
+    type Result = ();
+    fn handle(&mut self, EnableBundling(config): EnableBundling, _ctx: &mut Self::Context) {
+        if self.bundling.get().is_none() {
+            debug!("{:?} bundling outgoing messages", self.id);
+        }
+        self.bundling.set(Some(config));
+    }
 }
 
 pub struct SetDispatcher<D: SendableDispatcher>(pub D);
@@ -162,6 +413,17 @@ impl<D: SendableDispatcher> Handler<SetDispatcher<D>> for Connection {
     }
 }
 
+impl Handler<ReportMisbehaviourTo> for Connection {
+    type Result = ();
+    fn handle(
+        &mut self,
+        ReportMisbehaviourTo(recipient): ReportMisbehaviourTo,
+        _ctx: &mut Self::Context,
+    ) {
+        self.misbehaviour = Some(recipient);
+    }
+}
+
 pub struct Payload<M: Into<network_envelope::Message>>(pub M);
 impl<M> actix::Message for Payload<M>
 where
@@ -175,11 +437,13 @@ where
 {
     type Result = Box<dyn Future<Item = (), Error = error::Error>>;
     fn handle(&mut self, Payload(msg): Payload<M>, _ctx: &mut Self::Context) -> Self::Result {
+        let msg: network_envelope::Message = msg.into();
+        self.traffic.sent(&msg);
         Box::new(
             self.writer
                 .clone()
                 .sink_from_err::<error::Error>()
-                .send(msg.into())
+                .send(msg)
                 .map(|_| ())
                 .map_err(|e| e.into()),
         )
@@ -196,44 +460,191 @@ impl<M> Handler<Request<M>> for Connection
 where
     M: Into<network_envelope::Message> + ResponseExtractor + 'static,
 {
-    type Result = Box<dyn Future<Item = <M as ResponseExtractor>::Response, Error = error::Error>>;
+    type Result = ResponseActFuture<Self, <M as ResponseExtractor>::Response, error::Error>;
     fn handle(&mut self, request: Request<M>, _: &mut Self::Context) -> Self::Result {
         let msg: network_envelope::Message = request.0.into();
         let correlation_id =
             Option::<CorrelationId>::from(&msg).expect("Request without correlation_id");
+        // KeepAlive counts unanswered pings itself, see --max-failed-pings
+        let counts_as_timeout = match msg {
+            network_envelope::Message::Ping(_) => false,
+            _ => true,
+        };
         let (send, receive) = oneshot::channel::<network_envelope::Message>();
         self.response_channels.insert(correlation_id.clone(), send);
+        self.traffic.sent(&msg);
         Box::new(
-            self.writer
-                .clone()
-                .sink_from_err::<error::Error>()
-                .send(msg)
-                .and_then(|_| {
-                    receive
-                        .map(|response| <M as ResponseExtractor>::extract(response))
-                        .map_err(|e| e.into())
-                }),
+            fut::wrap_future(
+                self.writer
+                    .clone()
+                    .sink_from_err::<error::Error>()
+                    .send(msg)
+                    .and_then(|_| receive.map_err(|e| e.into()))
+                    .timeout(REQUEST_TIMEOUT)
+                    .map_err(error::Error::from),
+            )
+            .then(move |result, connection: &mut Connection, ctx| {
+                match result {
+                    Ok(_) => connection.request_timeouts = 0,
+                    Err(error::Error::RequestTimeout) => {
+                        connection.request_timed_out(&correlation_id, counts_as_timeout, ctx)
+                    }
+                    Err(_) => {
+                        connection.response_channels.remove(&correlation_id);
+                    }
+                }
+                // A peer can answer with the right nonce but the wrong message
+                fut::result(result.and_then(|msg| {
+                    <M as ResponseExtractor>::extract(msg).ok_or(error::Error::UnexpectedResponse)
+                }))
+            }),
         )
     }
 }
 pub struct Shutdown(pub CloseConnectionReason);
 impl actix::Message for Shutdown {
-    type Result = ();
+    type Result = Result<(), ()>;
 }
 impl Handler<Shutdown> for Connection {
-    type Result = ();
-    fn handle(&mut self, Shutdown(reason): Shutdown, ctx: &mut Self::Context) {
+    type Result = ResponseActFuture<Self, (), ()>;
+    fn handle(&mut self, Shutdown(reason): Shutdown, _ctx: &mut Self::Context) -> Self::Result {
         let reason: String = reason.into();
         info!("Shutting down {:?} because {}", self.id, reason);
-        ctx.spawn(
+        let msg: network_envelope::Message = CloseConnectionMessage { reason: reason }.into();
+        self.traffic.sent(&msg);
+        // Resolves once the CloseConnectionMessage has been written
+        Box::new(
             fut::wrap_future(
                 self.writer
                     .clone()
                     .sink_from_err::<error::Error>()
-                    .send(CloseConnectionMessage { reason: reason }.into())
+                    .send(msg)
                     .then(|_| Ok(())),
             )
             .then(|_: Result<(), ()>, _, ctx: &mut Self::Context| fut::ok(ctx.stop())),
+        )
+    }
+}
+
+pub struct GetStats;
+impl actix::Message for GetStats {
+    type Result = ConnectionStats;
+}
+impl Handler<GetStats> for Connection {
+    type Result = MessageResult<GetStats>;
+    fn handle(&mut self, _: GetStats, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.traffic.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bisq::constants::BaseCurrencyNetwork;
+    use std::sync::{Arc, Mutex};
+    use tokio::codec::FramedRead;
+    use transport::Memory;
+
+    #[derive(Clone, Default)]
+    struct Consume(Arc<Mutex<Vec<network_envelope::Message>>>);
+    impl Dispatcher for Consume {
+        fn dispatch(&self, _: ConnectionId, _: network_envelope::Message) -> Dispatch {
+            Dispatch::Consumed
+        }
+        fn received(&self, _: ConnectionId, msg: &network_envelope::Message) {
+            self.0.lock().unwrap().push(msg.clone());
+        }
+    }
+
+    #[test]
+    fn request_over_memory_transport() {
+        let memory = Memory::new();
+        let seed = NodeAddress {
+            host_name: "seed".to_string(),
+            port: 2002,
+        };
+        // Scripted peer that answers the first Ping with a Pong
+        let peer = memory
+            .listen(seed.clone())
+            .into_future()
+            .map_err(|_| ())
+            .and_then(|(stream, _)| {
+                let (reader, writer) = stream.expect("No incoming connection").split();
+                FramedRead::new(reader, EnvelopeCodec::new(DEFAULT_MAX_FRAME_SIZE))
+                    .into_future()
+                    .map_err(|_| ())
+                    .and_then(move |(envelope, _)| {
+                        let request_nonce = match envelope.and_then(|e| e.message) {
+                            Some(network_envelope::Message::Ping(ping)) => ping.nonce,
+                            msg => panic!("Expected Ping got {:?}", msg),
+                        };
+                        FramedWrite::new(writer, EnvelopeCodec::new(DEFAULT_MAX_FRAME_SIZE))
+                            .send(NetworkEnvelope {
+                                message_version: MessageVersion::from(
+                                    BaseCurrencyNetwork::BtcRegtest,
+                                )
+                                .into(),
+                                message: Some(Pong { request_nonce }.into()),
+                            })
+                            .map(|_| ())
+                            .map_err(|_| ())
+                    })
+            });
+        let consume = Consume::default();
+        let received = consume.0.clone();
+        let pong = System::new("test")
+            .block_on(future::lazy(move || {
+                Arbiter::spawn(peer);
+                Connection::open(
+                    seed,
+                    BaseCurrencyNetwork::BtcRegtest.into(),
+                    consume,
+                    &memory,
+                    DEFAULT_MAX_FRAME_SIZE,
+                )
+                .and_then(|(_, conn)| {
+                    conn.send(Request(Ping {
+                        nonce: 7,
+                        last_round_trip_time: 0,
+                    }))
+                    .flatten()
+                })
+            }))
+            .unwrap();
+        assert_eq!(pong.request_nonce, 7);
+        // Responses bypass dispatch but are still seen, e.g. by the capture
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![network_envelope::Message::from(pong)]
         );
     }
+
+    #[test]
+    fn bundles_fit_into_frames() {
+        let version = MessageVersion::from(BaseCurrencyNetwork::BtcRegtest);
+        let ping = |nonce| -> network_envelope::Message {
+            Ping {
+                nonce,
+                last_round_trip_time: 0,
+            }
+            .into()
+        };
+        let single_len =
+            into_envelopes(vec![ping(1)], version, DEFAULT_MAX_FRAME_SIZE)[0].encoded_len();
+        let max_frame_size = BUNDLE_OVERHEAD + 3 * (single_len + 2);
+        let envelopes = into_envelopes((1..=10).map(ping).collect(), version, max_frame_size);
+        assert!(envelopes.len() > 1);
+        assert!(envelopes.iter().all(|e| e.encoded_len() <= max_frame_size));
+        let bundled: usize = envelopes
+            .iter()
+            .map(|e| match e.message.as_ref() {
+                Some(network_envelope::Message::BundleOfEnvelopes(bundle)) => {
+                    bundle.envelopes.len()
+                }
+                _ => 1,
+            })
+            .sum();
+        assert_eq!(bundled, 10);
+        assert!(into_envelopes(vec![ping(1)], version, single_len - 1).is_empty());
+    }
 }
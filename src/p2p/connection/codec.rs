@@ -0,0 +1,187 @@
+use crate::{bisq::payload::NetworkEnvelope, error};
+use bytes::BytesMut;
+use prost::{
+    encoding::{decode_varint, encoded_len_varint},
+    Message,
+};
+use std::io::Cursor;
+use tokio::codec::{Decoder, Encoder};
+
+const MAX_VARINT_LEN: usize = 10;
+const BUNDLE_OF_ENVELOPES_TAG: u64 = 43;
+const ENVELOPES_TAG: u64 = 1;
+// prost decodes nested messages recursively, so a peer could
+// exhaust the stack by sending bundles of bundles of bundles
+const MAX_BUNDLE_DEPTH: usize = 4;
+
+pub struct EnvelopeCodec {
+    max_frame_size: usize,
+}
+impl EnvelopeCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+}
+
+impl Decoder for EnvelopeCodec {
+    type Item = NetworkEnvelope;
+    type Error = error::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<NetworkEnvelope>, error::Error> {
+        let header_len = match src.iter().take(MAX_VARINT_LEN).position(|b| b & 0x80 == 0) {
+            Some(pos) => pos + 1,
+            None if src.len() < MAX_VARINT_LEN => return Ok(None),
+            None => return Err(prost::DecodeError::new("invalid varint").into()),
+        };
+        let size = decode_varint(&mut Cursor::new(&src[..header_len]))?;
+        if size > self.max_frame_size as u64 {
+            return Err(error::Error::FrameTooLarge(size));
+        }
+        let frame_len = header_len + size as usize;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+        src.split_to(header_len);
+        let frame = src.split_to(size as usize);
+        if bundles_too_deep(Cursor::new(&frame[..]), 0) {
+            return Err(prost::DecodeError::new("bundles nested too deeply").into());
+        }
+        Ok(Some(NetworkEnvelope::decode(&frame[..])?))
+    }
+}
+
+fn bundles_too_deep(mut envelope: Cursor<&[u8]>, depth: usize) -> bool {
+    while let Some((tag, bundle)) = next_field(&mut envelope) {
+        if tag != BUNDLE_OF_ENVELOPES_TAG {
+            continue;
+        }
+        if depth == MAX_BUNDLE_DEPTH {
+            return true;
+        }
+        let mut bundle = Cursor::new(bundle);
+        while let Some((tag, nested)) = next_field(&mut bundle) {
+            if tag == ENVELOPES_TAG && bundles_too_deep(Cursor::new(nested), depth + 1) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Returns the tag and the contents of length delimited fields.
+// Malformed input ends the scan and is left for prost to reject.
+fn next_field<'a>(buf: &mut Cursor<&'a [u8]>) -> Option<(u64, &'a [u8])> {
+    let key = decode_varint(buf).ok()?;
+    let len = match key & 0x7 {
+        0 => decode_varint(buf).map(|_| 0).ok()?,
+        1 => 8,
+        2 => decode_varint(buf).ok()?,
+        5 => 4,
+        _ => return None,
+    };
+    let data: &'a [u8] = *buf.get_ref();
+    let start = buf.position() as usize;
+    if len > (data.len() - start) as u64 {
+        return None;
+    }
+    let end = start + len as usize;
+    buf.set_position(end as u64);
+    Some((key >> 3, &data[start..end]))
+}
+
+impl Encoder for EnvelopeCodec {
+    type Item = NetworkEnvelope;
+    type Error = error::Error;
+
+    fn encode(
+        &mut self,
+        envelope: NetworkEnvelope,
+        dst: &mut BytesMut,
+    ) -> Result<(), error::Error> {
+        let len = envelope.encoded_len();
+        if len > self.max_frame_size {
+            return Err(error::Error::FrameTooLarge(len as u64));
+        }
+        dst.reserve(len + encoded_len_varint(len as u64));
+        envelope.encode_length_delimited(dst)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bisq::payload::{network_envelope, BundleOfEnvelopes, Ping};
+
+    fn ping_envelope() -> NetworkEnvelope {
+        NetworkEnvelope {
+            message_version: 10,
+            message: Some(network_envelope::Message::Ping(Ping {
+                nonce: 42,
+                last_round_trip_time: 0,
+            })),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut codec = EnvelopeCodec::new(1024);
+        let mut buf = BytesMut::new();
+        codec.encode(ping_envelope(), &mut buf).unwrap();
+        codec.encode(ping_envelope(), &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping_envelope()));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping_envelope()));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn partial_frame() {
+        let mut codec = EnvelopeCodec::new(1024);
+        let mut encoded = BytesMut::new();
+        codec.encode(ping_envelope(), &mut encoded).unwrap();
+        let mut buf = BytesMut::new();
+        for byte in encoded[..encoded.len() - 1].iter() {
+            buf.extend_from_slice(&[*byte]);
+            assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        }
+        buf.extend_from_slice(&encoded[encoded.len() - 1..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping_envelope()));
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let mut codec = EnvelopeCodec::new(1024);
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
+        match codec.decode(&mut buf) {
+            Err(error::Error::FrameTooLarge(_)) => (),
+            res => panic!("Expected FrameTooLarge got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn nested_bundles() {
+        let mut envelope = ping_envelope();
+        let mut nest = |depth| {
+            for _ in 0..depth {
+                envelope = NetworkEnvelope {
+                    message_version: 10,
+                    message: Some(network_envelope::Message::BundleOfEnvelopes(
+                        BundleOfEnvelopes {
+                            envelopes: vec![envelope.clone()],
+                        },
+                    )),
+                };
+            }
+            envelope.clone()
+        };
+        let mut codec = EnvelopeCodec::new(1024);
+        let mut buf = BytesMut::new();
+        let allowed = nest(MAX_BUNDLE_DEPTH);
+        codec.encode(allowed.clone(), &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(allowed));
+        codec.encode(nest(1), &mut buf).unwrap();
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
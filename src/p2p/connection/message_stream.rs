@@ -1,3 +1,4 @@
+use super::codec::EnvelopeCodec;
 use crate::{
     bisq::payload::{network_envelope, NetworkEnvelope},
     error,
@@ -7,117 +8,63 @@ use crate::{
         Async, Stream,
     },
 };
-use futures::try_ready;
-use prost::{encoding::decode_varint, Message};
-use std::{collections::VecDeque, io};
+use std::collections::VecDeque;
+use tokio::codec::FramedRead;
 
-enum MessageStreamState {
-    MessageInProgress {
-        size: usize,
-        pos: usize,
-        buf: Vec<u8>,
-    },
-    BetweenMessages {
-        buf: [u8; 10],
-        pos: usize,
-    },
-    Empty,
-}
-pub struct MessageStream {
-    reader: ReadHalf<TcpStream>,
-    state: MessageStreamState,
+pub struct MessageStream<R: AsyncRead = ReadHalf<TcpStream>> {
+    frames: FramedRead<R, EnvelopeCodec>,
     buffer: VecDeque<NetworkEnvelope>,
+    failed: bool,
 }
-impl MessageStream {
-    pub fn new(reader: ReadHalf<TcpStream>) -> MessageStream {
+impl<R: AsyncRead> MessageStream<R> {
+    pub fn new(reader: R, max_frame_size: usize) -> MessageStream<R> {
         MessageStream {
-            reader,
-            state: MessageStreamState::BetweenMessages {
-                buf: [0; 10],
-                pos: 0,
-            },
+            frames: FramedRead::new(reader, EnvelopeCodec::new(max_frame_size)),
             buffer: VecDeque::new(),
+            failed: false,
         }
     }
+    // Iterative so a bundle full of empty envelopes can't exhaust the stack
     fn next_from_buffer(&mut self) -> Option<network_envelope::Message> {
-        let msg = self.buffer.pop_front()?.message;
-        match msg {
-            Some(network_envelope::Message::BundleOfEnvelopes(msg)) => {
-                msg.envelopes
+        while let Some(envelope) = self.buffer.pop_front() {
+            match envelope.message {
+                Some(network_envelope::Message::BundleOfEnvelopes(msg)) => msg
+                    .envelopes
                     .into_iter()
                     .rev()
-                    .for_each(|envelope| self.buffer.push_front(envelope));
-                self.next_from_buffer()
+                    .for_each(|envelope| self.buffer.push_front(envelope)),
+                None => (),
+                msg => return msg,
             }
-            None => self.next_from_buffer(),
-            _ => msg,
         }
+        None
     }
 }
-impl Stream for MessageStream {
+impl<R: AsyncRead> Stream for MessageStream<R> {
     type Item = network_envelope::Message;
     type Error = error::Error;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
-        if let Some(msg) = self.next_from_buffer() {
-            debug!("Receiving msg: {:?}", msg);
-            return Ok(Async::Ready(Some(msg)));
+        // After a failure the stream stays pending so the connection
+        // can still tell the peer why it is closing before it stops.
+        if self.failed {
+            return Ok(Async::NotReady);
         }
-        let next_read = match self.state {
-            MessageStreamState::Empty => panic!("Stream is already finished"),
-            MessageStreamState::BetweenMessages {
-                ref mut buf,
-                ref mut pos,
-            } => {
-                while *pos < buf.len() {
-                    let n = try_ready!(self.reader.poll_read(&mut buf[*pos..(*pos + 1)]));
-                    if n == 0 {
-                        self.state = MessageStreamState::Empty;
-                        return Err(
-                            io::Error::new(io::ErrorKind::UnexpectedEof, "early eof").into()
-                        );
-                    }
-                    let old_pos = *pos;
-                    *pos += n;
-                    if buf[old_pos] & 0b10000000 == 0 {
-                        break;
-                    }
-                }
-                let mut size_reader: VecDeque<u8> = buf.iter().take(*pos).cloned().collect();
-                let size = decode_varint(&mut size_reader)? as usize;
-                let buf = vec![0; size];
-                self.state = MessageStreamState::MessageInProgress { size, pos: 0, buf };
-                return self.poll();
+        loop {
+            if let Some(msg) = self.next_from_buffer() {
+                debug!("Receiving msg: {:?}", msg);
+                return Ok(Async::Ready(Some(msg)));
             }
-            MessageStreamState::MessageInProgress {
-                ref mut size,
-                ref mut pos,
-                ref mut buf,
-            } => {
-                while *pos < *size {
-                    let n = try_ready!(self.reader.poll_read(&mut buf[*pos..]));
-                    *pos += n;
-                    if n == 0 {
-                        return Err(
-                            io::Error::new(io::ErrorKind::UnexpectedEof, "early eof").into()
-                        );
-                    }
-                }
-                match NetworkEnvelope::decode(&*buf) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        self.state = MessageStreamState::Empty;
-                        debug!("Decode error {:?}", e);
-                        return Err(e.into());
-                    }
+            match self.frames.poll() {
+                Ok(Async::Ready(Some(envelope))) => self.buffer.push_back(envelope),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    debug!("Decode error {:?}", e);
+                    self.failed = true;
+                    return Err(e);
                 }
             }
-        };
-        self.buffer.push_back(next_read);
-        self.state = MessageStreamState::BetweenMessages {
-            buf: [0; 10],
-            pos: 0,
-        };
-        self.poll()
+        }
     }
 }
@@ -0,0 +1,352 @@
+use crate::{
+    error,
+    prelude::{
+        future::Either,
+        io::{read_exact, write_all},
+        net::TcpStream,
+        *,
+    },
+};
+use std::net::{IpAddr, SocketAddr};
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+#[derive(Debug)]
+pub enum SocksError {
+    InvalidResponse,
+    NoAcceptableAuthMethod,
+    HostnameTooLong,
+    CredentialsTooLong,
+    AuthenticationFailed,
+    GeneralFailure,
+    NotAllowed,
+    NetworkUnreachable,
+    HostUnreachable,
+    ConnectionRefused,
+    TtlExpired,
+    CommandNotSupported,
+    AddressTypeNotSupported,
+    UnknownReply(u8),
+}
+impl SocksError {
+    fn from_reply(reply: u8) -> Self {
+        use SocksError::*;
+        match reply {
+            1 => GeneralFailure,
+            2 => NotAllowed,
+            3 => NetworkUnreachable,
+            4 => HostUnreachable,
+            5 => ConnectionRefused,
+            6 => TtlExpired,
+            7 => CommandNotSupported,
+            8 => AddressTypeNotSupported,
+            reply => UnknownReply(reply),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+impl Credentials {
+    // Tor keeps streams with different credentials on separate circuits (IsolateSOCKSAuth)
+    pub fn isolation(token: String) -> Self {
+        Self {
+            username: "risq".to_string(),
+            password: token,
+        }
+    }
+
+    fn auth_request(&self) -> Result<Vec<u8>, error::Error> {
+        let max_len = u8::max_value() as usize;
+        if self.username.len() > max_len || self.password.len() > max_len {
+            return Err(SocksError::CredentialsTooLong.into());
+        }
+        let mut request = vec![USERNAME_PASSWORD_VERSION, self.username.len() as u8];
+        request.extend(self.username.as_bytes());
+        request.push(self.password.len() as u8);
+        request.extend(self.password.as_bytes());
+        Ok(request)
+    }
+}
+
+// Connects to host:port through the proxy. The host is resolved by the proxy
+// which is what makes onion addresses reachable.
+pub fn connect(
+    proxy: SocketAddr,
+    host: &str,
+    port: u16,
+    credentials: Option<Credentials>,
+) -> impl Future<Item = TcpStream, Error = error::Error> {
+    future::result(connect_request(host, port).and_then(|request| {
+        let auth_request = match credentials {
+            Some(credentials) => Some(credentials.auth_request()?),
+            None => None,
+        };
+        Ok((request, auth_request))
+    }))
+    .and_then(move |(request, auth_request)| {
+        let method = match auth_request {
+            Some(_) => USERNAME_PASSWORD,
+            None => NO_AUTHENTICATION,
+        };
+        TcpStream::connect(&proxy)
+            .and_then(move |tcp| write_all(tcp, [SOCKS_VERSION, 1, method]))
+            .and_then(|(tcp, _)| read_exact(tcp, [0u8; 2]))
+            .map_err(error::Error::from)
+            .and_then(move |(tcp, method_selection)| match method_selection {
+                [SOCKS_VERSION, selected] if selected == method => Ok(tcp),
+                [SOCKS_VERSION, NO_ACCEPTABLE_METHODS] => {
+                    Err(SocksError::NoAcceptableAuthMethod.into())
+                }
+                _ => Err(SocksError::InvalidResponse.into()),
+            })
+            .and_then(|tcp| match auth_request {
+                Some(auth_request) => Either::A(authenticate(tcp, auth_request)),
+                None => Either::B(future::ok(tcp)),
+            })
+            .and_then(move |tcp| write_all(tcp, request).map_err(error::Error::from))
+    })
+    .and_then(|(tcp, _)| read_exact(tcp, [0u8; 4]).map_err(error::Error::from))
+    .and_then(|(tcp, reply)| match reply {
+        [SOCKS_VERSION, 0, 0, address_type] => Ok((tcp, address_type)),
+        [SOCKS_VERSION, 0, _, _] => Err(SocksError::InvalidResponse.into()),
+        [SOCKS_VERSION, reply, _, _] => Err(SocksError::from_reply(reply).into()),
+        _ => Err(SocksError::InvalidResponse.into()),
+    })
+    .and_then(|(tcp, address_type)| match address_type {
+        ATYP_IPV4 => Either::A(future::ok((tcp, 4))),
+        ATYP_IPV6 => Either::A(future::ok((tcp, 16))),
+        ATYP_DOMAIN => Either::B(
+            read_exact(tcp, [0u8; 1])
+                .map(|(tcp, [len])| (tcp, len as usize))
+                .map_err(error::Error::from),
+        ),
+        _ => Either::A(future::err(SocksError::InvalidResponse.into())),
+    })
+    // Skip the bound address and port, the stream is ready to use after that
+    .and_then(|(tcp, bound_addr_len)| {
+        read_exact(tcp, vec![0u8; bound_addr_len + 2])
+            .map(|(tcp, _)| tcp)
+            .map_err(error::Error::from)
+    })
+}
+
+fn authenticate(
+    tcp: TcpStream,
+    auth_request: Vec<u8>,
+) -> impl Future<Item = TcpStream, Error = error::Error> {
+    write_all(tcp, auth_request)
+        .and_then(|(tcp, _)| read_exact(tcp, [0u8; 2]))
+        .map_err(error::Error::from)
+        .and_then(|(tcp, status)| match status {
+            [USERNAME_PASSWORD_VERSION, 0] => Ok(tcp),
+            [USERNAME_PASSWORD_VERSION, _] => Err(SocksError::AuthenticationFailed.into()),
+            _ => Err(SocksError::InvalidResponse.into()),
+        })
+}
+
+fn connect_request(host: &str, port: u16) -> Result<Vec<u8>, error::Error> {
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > u8::max_value() as usize {
+                return Err(SocksError::HostnameTooLong.into());
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend(host.as_bytes());
+        }
+    }
+    request.extend(&port.to_be_bytes());
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    // Accepts a single client, checks the handshake and answers with the given reply
+    fn fake_proxy(expected_request: Vec<u8>, reply: Vec<u8>) -> (SocketAddr, JoinHandle<()>) {
+        fake_authenticating_proxy(None, expected_request, reply)
+    }
+
+    fn fake_authenticating_proxy(
+        expected_auth: Option<(Vec<u8>, u8)>,
+        expected_request: Vec<u8>,
+        reply: Vec<u8>,
+    ) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            let method = match expected_auth {
+                Some(_) => USERNAME_PASSWORD,
+                None => NO_AUTHENTICATION,
+            };
+            assert_eq!(greeting, [SOCKS_VERSION, 1, method]);
+            stream.write_all(&[SOCKS_VERSION, method]).unwrap();
+            if let Some((expected_auth, status)) = expected_auth {
+                let mut auth = vec![0u8; expected_auth.len()];
+                stream.read_exact(&mut auth).unwrap();
+                assert_eq!(auth, expected_auth);
+                stream
+                    .write_all(&[USERNAME_PASSWORD_VERSION, status])
+                    .unwrap();
+                if status != 0 {
+                    return;
+                }
+            }
+            let mut request = vec![0u8; expected_request.len()];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, expected_request);
+            stream.write_all(&reply).unwrap();
+            stream.write_all(b"payload").unwrap();
+        });
+        (addr, handle)
+    }
+
+    fn onion_request() -> Vec<u8> {
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0, ATYP_DOMAIN, 22];
+        request.extend(b"5quyxpxheyvzmb2d.onion");
+        request.extend(&[0x23, 0x8c]);
+        request
+    }
+
+    #[test]
+    fn connect_to_onion() {
+        let (proxy, server) = fake_proxy(
+            onion_request(),
+            vec![SOCKS_VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+        );
+        let payload = runtime::Runtime::new()
+            .unwrap()
+            .block_on(
+                connect(proxy, "5quyxpxheyvzmb2d.onion", 9100, None)
+                    .and_then(|tcp| read_exact(tcp, [0u8; 7]).map_err(error::Error::from)),
+            )
+            .unwrap()
+            .1;
+        server.join().unwrap();
+        assert_eq!(&payload, b"payload");
+    }
+
+    #[test]
+    fn connect_to_ip() {
+        let (proxy, server) = fake_proxy(
+            vec![
+                SOCKS_VERSION,
+                CMD_CONNECT,
+                0,
+                ATYP_IPV4,
+                127,
+                0,
+                0,
+                1,
+                0x13,
+                0x88,
+            ],
+            vec![SOCKS_VERSION, 0, 0, ATYP_DOMAIN, 3, b'f', b'o', b'o', 0, 0],
+        );
+        runtime::Runtime::new()
+            .unwrap()
+            .block_on(connect(proxy, "127.0.0.1", 5000, None))
+            .unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn host_unreachable() {
+        let (proxy, server) = fake_proxy(
+            onion_request(),
+            vec![SOCKS_VERSION, 4, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+        );
+        match runtime::Runtime::new().unwrap().block_on(connect(
+            proxy,
+            "5quyxpxheyvzmb2d.onion",
+            9100,
+            None,
+        )) {
+            Err(error::Error::Socks(SocksError::HostUnreachable)) => (),
+            res => panic!("Expected HostUnreachable got {:?}", res.map(|_| ())),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn hostname_too_long() {
+        match connect_request(&"a".repeat(256), 5000) {
+            Err(error::Error::Socks(SocksError::HostnameTooLong)) => (),
+            res => panic!("Expected HostnameTooLong got {:?}", res),
+        }
+    }
+
+    fn isolation_auth() -> Vec<u8> {
+        let mut auth = vec![USERNAME_PASSWORD_VERSION, 4];
+        auth.extend(b"risq");
+        auth.push(27);
+        auth.extend(b"5quyxpxheyvzmb2d.onion:9100");
+        auth
+    }
+
+    fn isolation() -> Option<Credentials> {
+        Some(Credentials::isolation(
+            "5quyxpxheyvzmb2d.onion:9100".to_string(),
+        ))
+    }
+
+    #[test]
+    fn isolated_stream() {
+        let (proxy, server) = fake_authenticating_proxy(
+            Some((isolation_auth(), 0)),
+            onion_request(),
+            vec![SOCKS_VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+        );
+        runtime::Runtime::new()
+            .unwrap()
+            .block_on(connect(proxy, "5quyxpxheyvzmb2d.onion", 9100, isolation()))
+            .unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn authentication_failed() {
+        let (proxy, server) =
+            fake_authenticating_proxy(Some((isolation_auth(), 1)), Vec::new(), Vec::new());
+        match runtime::Runtime::new().unwrap().block_on(connect(
+            proxy,
+            "5quyxpxheyvzmb2d.onion",
+            9100,
+            isolation(),
+        )) {
+            Err(error::Error::Socks(SocksError::AuthenticationFailed)) => (),
+            res => panic!("Expected AuthenticationFailed got {:?}", res.map(|_| ())),
+        }
+        server.join().unwrap();
+    }
+}
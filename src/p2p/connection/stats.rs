@@ -0,0 +1,158 @@
+use crate::{
+    bisq::payload::network_envelope,
+    prelude::{
+        io::{AsyncRead, AsyncWrite},
+        Poll,
+    },
+};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io::{self, Read, Write},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    pub age: Duration,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub messages_received: HashMap<&'static str, u64>,
+    pub messages_sent: HashMap<&'static str, u64>,
+    pub retained_messages: u64,
+    pub dropped_messages: u64,
+    pub last_received_at: Option<SystemTime>,
+}
+
+pub struct Traffic {
+    established_at: Instant,
+    bytes_received: Rc<Cell<u64>>,
+    bytes_sent: Rc<Cell<u64>>,
+    messages_received: HashMap<&'static str, u64>,
+    messages_sent: HashMap<&'static str, u64>,
+    retained_messages: u64,
+    dropped_messages: u64,
+    last_received_at: Option<SystemTime>,
+}
+impl Traffic {
+    pub fn new(bytes_received: Rc<Cell<u64>>, bytes_sent: Rc<Cell<u64>>) -> Self {
+        Self {
+            established_at: Instant::now(),
+            bytes_received,
+            bytes_sent,
+            messages_received: HashMap::new(),
+            messages_sent: HashMap::new(),
+            retained_messages: 0,
+            dropped_messages: 0,
+            last_received_at: None,
+        }
+    }
+
+    pub fn received(&mut self, msg: &network_envelope::Message) {
+        *self.messages_received.entry(msg.name()).or_insert(0) += 1;
+        self.last_received_at = Some(SystemTime::now());
+    }
+
+    pub fn sent(&mut self, msg: &network_envelope::Message) {
+        *self.messages_sent.entry(msg.name()).or_insert(0) += 1;
+    }
+
+    pub fn retained(&mut self) {
+        self.retained_messages += 1;
+    }
+
+    pub fn dropped(&mut self) {
+        self.dropped_messages += 1;
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            age: self.established_at.elapsed(),
+            bytes_received: self.bytes_received.get(),
+            bytes_sent: self.bytes_sent.get(),
+            messages_received: self.messages_received.clone(),
+            messages_sent: self.messages_sent.clone(),
+            retained_messages: self.retained_messages,
+            dropped_messages: self.dropped_messages,
+            last_received_at: self.last_received_at,
+        }
+    }
+}
+
+// Adds up the bytes going through the wrapped reader or writer
+pub struct Counted<T> {
+    inner: T,
+    count: Rc<Cell<u64>>,
+}
+impl<T> Counted<T> {
+    pub fn new(inner: T, count: Rc<Cell<u64>>) -> Self {
+        Self { inner, count }
+    }
+
+    fn add(&self, n: usize) {
+        self.count.set(self.count.get() + n as u64);
+    }
+}
+impl<T: Read> Read for Counted<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.add(n);
+        Ok(n)
+    }
+}
+impl<T: AsyncRead> AsyncRead for Counted<T> {}
+impl<T: Write> Write for Counted<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.add(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+impl<T: AsyncWrite> AsyncWrite for Counted<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bisq::payload::Ping;
+
+    #[test]
+    fn counts_bytes() {
+        let count = Rc::new(Cell::new(0));
+        let mut reader = Counted::new(&b"hello world"[..], count.clone());
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).unwrap();
+        let mut writer = Counted::new(Vec::new(), count.clone());
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(count.get(), 8);
+    }
+
+    #[test]
+    fn counts_messages_per_kind() {
+        let mut traffic = Traffic::new(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let ping: network_envelope::Message = Ping {
+            nonce: 1,
+            last_round_trip_time: 0,
+        }
+        .into();
+        traffic.received(&ping);
+        traffic.received(&ping);
+        traffic.sent(&ping);
+        traffic.retained();
+        traffic.dropped();
+        let stats = traffic.stats();
+        assert_eq!(stats.messages_received.get("Ping"), Some(&2));
+        assert_eq!(stats.messages_sent.get("Ping"), Some(&1));
+        assert_eq!(stats.retained_messages, 1);
+        assert_eq!(stats.dropped_messages, 1);
+        assert!(stats.last_received_at.is_some());
+    }
+}
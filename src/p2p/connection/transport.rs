@@ -0,0 +1,239 @@
+use super::socks::{self, Credentials};
+use crate::{
+    bisq::payload::NodeAddress,
+    error,
+    prelude::{
+        io::{AsyncRead, AsyncWrite},
+        net::TcpStream,
+        sync::mpsc,
+        *,
+    },
+};
+use futures::task::{self, Task};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{Arc, Mutex},
+};
+
+pub trait Duplex: AsyncRead + AsyncWrite + Send + 'static {}
+impl<T: AsyncRead + AsyncWrite + Send + 'static> Duplex for T {}
+
+pub type Connect = Box<dyn Future<Item = Box<dyn Duplex>, Error = error::Error>>;
+
+pub trait Transport: Send + Sync {
+    fn connect(&self, addr: &NodeAddress) -> Connect;
+}
+
+pub fn from_proxy(proxy: Option<SocketAddr>) -> Arc<dyn Transport> {
+    match proxy {
+        Some(proxy) => Arc::new(Socks { proxy }),
+        None => Arc::new(Tcp),
+    }
+}
+
+pub struct Tcp;
+impl Transport for Tcp {
+    fn connect(&self, addr: &NodeAddress) -> Connect {
+        Box::new(
+            future::result(addr.to_socket_addrs().and_then(|mut addrs| {
+                addrs.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "Couldn't resolve address")
+                })
+            }))
+            .and_then(|socket_addr| TcpStream::connect(&socket_addr))
+            .map(|tcp| Box::new(tcp) as Box<dyn Duplex>)
+            .map_err(error::Error::from),
+        )
+    }
+}
+
+pub struct Socks {
+    pub proxy: SocketAddr,
+}
+impl Transport for Socks {
+    fn connect(&self, addr: &NodeAddress) -> Connect {
+        Box::new(
+            socks::connect(
+                self.proxy,
+                addr.host(),
+                addr.port as u16,
+                Some(Credentials::isolation(addr.to_string())),
+            )
+            .map(|tcp| Box::new(tcp) as Box<dyn Duplex>),
+        )
+    }
+}
+
+// Routes connections to listeners in the same process so peers can be scripted in tests
+#[derive(Clone, Default)]
+pub struct Memory {
+    listeners: Arc<Mutex<HashMap<NodeAddress, mpsc::UnboundedSender<Box<dyn Duplex>>>>>,
+}
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn listen(&self, addr: NodeAddress) -> mpsc::UnboundedReceiver<Box<dyn Duplex>> {
+        let (send, rec) = mpsc::unbounded_channel();
+        self.listeners.lock().unwrap().insert(addr, send);
+        rec
+    }
+}
+impl Transport for Memory {
+    fn connect(&self, addr: &NodeAddress) -> Connect {
+        let (local, remote) = duplex();
+        let accepted = self
+            .listeners
+            .lock()
+            .unwrap()
+            .get_mut(addr)
+            .map_or(false, |listener| {
+                listener.try_send(Box::new(remote)).is_ok()
+            });
+        Box::new(future::result(if accepted {
+            Ok(Box::new(local) as Box<dyn Duplex>)
+        } else {
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "Nobody listening").into())
+        }))
+    }
+}
+
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+    reader: Option<Task>,
+}
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(reader) = self.reader.take() {
+            reader.notify();
+        }
+    }
+}
+
+pub struct MemoryStream {
+    incoming: Arc<Mutex<Pipe>>,
+    outgoing: Arc<Mutex<Pipe>>,
+}
+pub fn duplex() -> (MemoryStream, MemoryStream) {
+    let a = Arc::new(Mutex::new(Pipe::default()));
+    let b = Arc::new(Mutex::new(Pipe::default()));
+    (
+        MemoryStream {
+            incoming: a.clone(),
+            outgoing: b.clone(),
+        },
+        MemoryStream {
+            incoming: b,
+            outgoing: a,
+        },
+    )
+}
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.incoming.lock().unwrap();
+        if pipe.buf.is_empty() {
+            if pipe.closed {
+                return Ok(0);
+            }
+            pipe.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = buf.len().min(pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+impl AsyncRead for MemoryStream {}
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.outgoing.lock().unwrap();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.buf.extend(buf);
+        if let Some(reader) = pipe.reader.take() {
+            reader.notify();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl AsyncWrite for MemoryStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.outgoing.lock().unwrap().close();
+        Ok(Async::Ready(()))
+    }
+}
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.outgoing.lock().unwrap().close();
+        self.incoming.lock().unwrap().close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{read_exact, read_to_end, write_all};
+
+    fn node(port: i32) -> NodeAddress {
+        NodeAddress {
+            host_name: "localhost".to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn memory_roundtrip() {
+        let memory = Memory::new();
+        let incoming = memory.listen(node(2002));
+        let exchange = memory
+            .connect(&node(2002))
+            .and_then(|local| write_all(local, b"ping").map_err(error::Error::from))
+            .join(
+                incoming
+                    .into_future()
+                    .map_err(|_| error::Error::ReceiveMPSCError),
+            )
+            .and_then(|((_local, _), (remote, _))| {
+                read_exact(remote.unwrap(), [0u8; 4]).map_err(error::Error::from)
+            });
+        let (_, buf) = runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(exchange)
+            .unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn memory_refuses_unknown_address() {
+        let memory = Memory::new();
+        let _incoming = memory.listen(node(2002));
+        let res = runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(memory.connect(&node(3002)));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn dropped_end_reads_eof() {
+        let (local, remote) = duplex();
+        drop(local);
+        let (_, buf) = runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(read_to_end(remote, Vec::new()))
+            .unwrap();
+        assert!(buf.is_empty());
+    }
+}
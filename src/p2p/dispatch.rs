@@ -3,13 +3,24 @@ use crate::{bisq::payload::*, prelude::*};
 use actix::dev::ToEnvelope;
 use std::marker::PhantomData;
 
+mod rate_limit;
+
+pub mod capture;
+
+pub use rate_limit::RateLimited;
+
 pub enum Dispatch {
     Consumed,
     Retained(network_envelope::Message),
+    // Rejected because the peer is sending too much of the same kind
+    Dropped,
+    Abusive,
 }
 
 pub trait Dispatcher {
     fn dispatch(&self, conn: ConnectionId, msg: network_envelope::Message) -> Dispatch;
+    // Sees every message a connection receives, including responses that never get dispatched
+    fn received(&self, _conn: ConnectionId, _msg: &network_envelope::Message) {}
 }
 pub trait SendableDispatcher: Dispatcher + Clone + Send + 'static {}
 impl<T: Dispatcher + Clone + Send + 'static> SendableDispatcher for T {}
@@ -97,8 +108,12 @@ impl<F: Dispatcher + Sized, N: Dispatcher + Sized> ForwardTo<F, N> {
 impl<F: Dispatcher + Sized, N: Dispatcher + Sized> Dispatcher for ForwardTo<F, N> {
     fn dispatch(&self, conn: ConnectionId, msg: network_envelope::Message) -> Dispatch {
         match self.first.dispatch(conn, msg) {
-            Dispatch::Consumed => Dispatch::Consumed,
             Dispatch::Retained(msg) => self.next.dispatch(conn, msg),
+            dispatch => dispatch,
         }
     }
+    fn received(&self, conn: ConnectionId, msg: &network_envelope::Message) {
+        self.first.received(conn, msg);
+        self.next.received(conn, msg);
+    }
 }
@@ -0,0 +1,181 @@
+use super::{Dispatch, Dispatcher};
+use crate::{
+    bisq::{
+        constants::BaseCurrencyNetwork,
+        payload::{network_envelope, MessageVersion, NetworkEnvelope},
+    },
+    error,
+    p2p::connection::ConnectionId,
+};
+use prost::{DecodeError, Message};
+use std::{
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{Cursor, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// One length delimited record per received message
+#[derive(Clone, PartialEq, Message)]
+struct CapturedEnvelope {
+    #[prost(bytes, tag = "1")]
+    connection_id: Vec<u8>,
+    #[prost(int64, tag = "2")]
+    received_at: i64,
+    #[prost(message, optional, tag = "3")]
+    envelope: Option<NetworkEnvelope>,
+}
+
+pub struct Record {
+    pub connection_id: ConnectionId,
+    pub received_at: SystemTime,
+    pub message: network_envelope::Message,
+}
+
+#[derive(Clone)]
+pub struct Capture {
+    file: Arc<Mutex<File>>,
+    message_version: MessageVersion,
+}
+impl Capture {
+    pub fn open(path: &Path, network: BaseCurrencyNetwork) -> Result<Self, error::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            message_version: network.into(),
+        })
+    }
+
+    fn append(
+        &self,
+        conn: ConnectionId,
+        msg: &network_envelope::Message,
+    ) -> Result<(), error::Error> {
+        let record = CapturedEnvelope {
+            connection_id: conn.into(),
+            received_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as i64),
+            envelope: Some(NetworkEnvelope {
+                message_version: self.message_version.into(),
+                message: Some(msg.clone()),
+            }),
+        };
+        let mut serialized = Vec::with_capacity(record.encoded_len() + 10);
+        record.encode_length_delimited(&mut serialized)?;
+        self.file
+            .lock()
+            .expect("Capture file poisoned")
+            .write_all(&serialized)?;
+        Ok(())
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<Record>, error::Error> {
+    let serialized = fs::read(path)?;
+    let len = serialized.len() as u64;
+    let mut cursor = Cursor::new(serialized);
+    let mut records = Vec::new();
+    while cursor.position() < len {
+        let captured = CapturedEnvelope::decode_length_delimited(&mut cursor)?;
+        let connection_id = ConnectionId::try_from(&captured.connection_id[..])
+            .map_err(|_| DecodeError::new("invalid connection id"))?;
+        if let Some(message) = captured.envelope.and_then(|envelope| envelope.message) {
+            records.push(Record {
+                connection_id,
+                received_at: UNIX_EPOCH + Duration::from_millis(captured.received_at as u64),
+                message,
+            });
+        }
+    }
+    Ok(records)
+}
+
+pub struct Captured<D: Dispatcher> {
+    inner: D,
+    capture: Option<Capture>,
+}
+impl<D: Dispatcher> Captured<D> {
+    pub fn new(inner: D, capture: Option<Capture>) -> Self {
+        Self { inner, capture }
+    }
+}
+impl<D: Dispatcher + Clone> Clone for Captured<D> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone(), self.capture.clone())
+    }
+}
+impl<D: Dispatcher> Dispatcher for Captured<D> {
+    fn dispatch(&self, conn: ConnectionId, msg: network_envelope::Message) -> Dispatch {
+        self.inner.dispatch(conn, msg)
+    }
+    fn received(&self, conn: ConnectionId, msg: &network_envelope::Message) {
+        if let Some(capture) = self.capture.as_ref() {
+            if let Err(e) = capture.append(conn, msg) {
+                warn!("Couldn't capture {}: {:?}", msg.name(), e);
+            }
+        }
+        self.inner.received(conn, msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bisq::payload::{gen_nonce, Ping};
+    use std::env;
+
+    #[derive(Clone)]
+    struct Ignore;
+    impl Dispatcher for Ignore {
+        fn dispatch(&self, _: ConnectionId, _: network_envelope::Message) -> Dispatch {
+            Dispatch::Consumed
+        }
+    }
+
+    fn ping(nonce: i32) -> network_envelope::Message {
+        Ping {
+            nonce,
+            last_round_trip_time: 0,
+        }
+        .into()
+    }
+
+    #[test]
+    fn captures_in_order() {
+        let path = env::temp_dir().join(format!("risq-capture-{}", gen_nonce()));
+        let first = ConnectionId::try_from(&[1u8; 16][..]).unwrap();
+        let second = ConnectionId::try_from(&[2u8; 16][..]).unwrap();
+        let capture = Capture::open(&path, BaseCurrencyNetwork::BtcRegtest).unwrap();
+        let dispatcher = Captured::new(Ignore, Some(capture));
+        dispatcher.received(first, &ping(1));
+        dispatcher.clone().received(second, &ping(2));
+        dispatcher.received(first, &ping(3));
+
+        let records = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let replayed: Vec<_> = records
+            .into_iter()
+            .map(|record| (record.connection_id, record.message))
+            .collect();
+        assert_eq!(
+            replayed,
+            vec![(first, ping(1)), (second, ping(2)), (first, ping(3))]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_capture() {
+        let path = env::temp_dir().join(format!("risq-capture-{}", gen_nonce()));
+        let capture = Capture::open(&path, BaseCurrencyNetwork::BtcRegtest).unwrap();
+        let id = ConnectionId::try_from(&[1u8; 16][..]).unwrap();
+        capture.append(id, &ping(1)).unwrap();
+        let mut serialized = fs::read(&path).unwrap();
+        serialized.pop();
+        fs::write(&path, serialized).unwrap();
+        assert!(read(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
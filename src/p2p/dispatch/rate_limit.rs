@@ -0,0 +1,196 @@
+use super::{Dispatch, Dispatcher};
+use crate::{bisq::payload::network_envelope, p2p::connection::ConnectionId};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// Dropping this many messages within the window counts as abuse
+const ABUSE_WINDOW: Duration = Duration::from_secs(60);
+const ABUSE_THRESHOLD: u32 = 500;
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    burst: f64,
+    per_sec: f64,
+}
+
+// Only gossip that needs verifying is limited, everything else passes through
+fn limit_for(msg: &network_envelope::Message) -> Option<Limit> {
+    match msg {
+        network_envelope::Message::AddDataMessage(_) => Some(Limit {
+            burst: 200.,
+            per_sec: 20.,
+        }),
+        network_envelope::Message::RefreshOfferMessage(_) => Some(Limit {
+            burst: 200.,
+            per_sec: 20.,
+        }),
+        network_envelope::Message::RemoveDataMessage(_)
+        | network_envelope::Message::RemoveMailboxDataMessage(_) => Some(Limit {
+            burst: 200.,
+            per_sec: 20.,
+        }),
+        network_envelope::Message::AddPersistableNetworkPayloadMessage(_) => Some(Limit {
+            burst: 100.,
+            per_sec: 10.,
+        }),
+        _ => None,
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+impl Bucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            refilled_at: now,
+        }
+    }
+
+    fn take(&mut self, limit: Limit, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_sec).min(limit.burst);
+        self.refilled_at = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Default)]
+struct Limits {
+    buckets: HashMap<&'static str, Bucket>,
+    window_start: Option<Instant>,
+    dropped_in_window: u32,
+}
+impl Limits {
+    fn check(&mut self, msg: &network_envelope::Message, now: Instant) -> Option<Dispatch> {
+        let limit = limit_for(msg)?;
+        let bucket = self
+            .buckets
+            .entry(msg.name())
+            .or_insert_with(|| Bucket::new(limit, now));
+        if bucket.take(limit, now) {
+            return None;
+        }
+        match self.window_start {
+            Some(start) if now.duration_since(start) <= ABUSE_WINDOW => (),
+            _ => {
+                self.window_start = Some(now);
+                self.dropped_in_window = 0;
+            }
+        }
+        self.dropped_in_window += 1;
+        if self.dropped_in_window >= ABUSE_THRESHOLD {
+            self.window_start = None;
+            Some(Dispatch::Abusive)
+        } else {
+            Some(Dispatch::Dropped)
+        }
+    }
+}
+
+pub struct RateLimited<D: Dispatcher> {
+    inner: D,
+    limits: RefCell<HashMap<ConnectionId, Limits>>,
+}
+impl<D: Dispatcher> RateLimited<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            limits: RefCell::new(HashMap::new()),
+        }
+    }
+}
+impl<D: Dispatcher + Clone> Clone for RateLimited<D> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone())
+    }
+}
+impl<D: Dispatcher> Dispatcher for RateLimited<D> {
+    fn dispatch(&self, conn: ConnectionId, msg: network_envelope::Message) -> Dispatch {
+        let verdict = self
+            .limits
+            .borrow_mut()
+            .entry(conn)
+            .or_default()
+            .check(&msg, Instant::now());
+        match verdict {
+            Some(verdict) => verdict,
+            None => self.inner.dispatch(conn, msg),
+        }
+    }
+    fn received(&self, conn: ConnectionId, msg: &network_envelope::Message) {
+        self.inner.received(conn, msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bisq::payload::{AddDataMessage, Ping, RemoveDataMessage};
+
+    fn add_data() -> network_envelope::Message {
+        AddDataMessage::default().into()
+    }
+    fn remove_data() -> network_envelope::Message {
+        RemoveDataMessage::default().into()
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limit = Limit {
+            burst: 2.,
+            per_sec: 1.,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::new(limit, start);
+        assert!(bucket.take(limit, start));
+        assert!(bucket.take(limit, start));
+        assert!(!bucket.take(limit, start));
+        assert!(bucket.take(limit, start + Duration::from_secs(1)));
+        assert!(!bucket.take(limit, start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn drops_excess_and_flags_abuse() {
+        for msg in vec![add_data(), remove_data()] {
+            let mut limits = Limits::default();
+            let now = Instant::now();
+            let mut dropped = 0;
+            let mut abusive = 0;
+            for _ in 0..(200 + ABUSE_THRESHOLD) {
+                match limits.check(&msg, now) {
+                    None => (),
+                    Some(Dispatch::Dropped) => dropped += 1,
+                    Some(Dispatch::Abusive) => abusive += 1,
+                    Some(_) => panic!("unexpected verdict"),
+                }
+            }
+            assert_eq!(dropped, ABUSE_THRESHOLD - 1);
+            assert_eq!(abusive, 1);
+        }
+    }
+
+    #[test]
+    fn ignores_other_messages() {
+        let mut limits = Limits::default();
+        let now = Instant::now();
+        let ping: network_envelope::Message = Ping {
+            nonce: 1,
+            last_round_trip_time: 0,
+        }
+        .into();
+        for _ in 0..1000 {
+            assert!(limits.check(&ping, now).is_none());
+        }
+    }
+}
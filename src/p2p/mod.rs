@@ -2,6 +2,7 @@ mod bootstrap;
 mod broadcast;
 mod connection;
 mod peers;
+mod reputation;
 mod tor;
 
 pub mod dispatch;
@@ -9,10 +10,23 @@ pub mod server;
 
 pub use bootstrap::Bootstrap;
 pub use broadcast::Broadcaster;
-pub use connection::{Connection, ConnectionId, Request};
-pub use peers::Peers;
+#[cfg(feature = "fuzz")]
+pub(crate) use connection::MessageStream;
+pub use connection::{
+    transport, BundleConfig, Connection, ConnectionId, ConnectionStats, Payload, Request,
+    SocksError, DEFAULT_MAX_FRAME_SIZE,
+};
+pub use peers::{Peers, PeersConfig};
 pub use server::TorConfig;
+pub use tor::OnionVersion;
 
 pub mod message {
-    pub use super::broadcast::Broadcast;
+    pub use super::broadcast::{Broadcast, CloseAll, SendTo};
+    pub use super::peers::{
+        ConnectionReport, GetConnectionReports, GetNetworkInfo, KnownPeer, NetworkInfo,
+        RequestUpdatedData,
+    };
+    pub use super::reputation::{
+        BannedPeer, GetBannedPeers, Misbehaviour, Offence, ReportMisbehaviourTo,
+    };
 }
@@ -2,34 +2,53 @@ mod keep_alive;
 
 use super::{
     broadcast::Broadcaster,
-    connection::*,
-    dispatch::{self, ActorDispatcher, Receive, SendableDispatcher},
+    connection::{transport::Transport, *},
+    dispatch::{self, ActorDispatcher, RateLimited, Receive, SendableDispatcher},
+    reputation::*,
     server::event::*,
 };
 use crate::{
     bisq::{
         constants::{
-            self, BaseCurrencyNetwork, Capability, CloseConnectionReason, LOCAL_CAPABILITIES,
+            local_capabilities, seed_nodes, BaseCurrencyNetwork, Capability, CloseConnectionReason,
         },
         payload::*,
+        persistence,
     },
+    error,
     prelude::{fut::Either, *},
 };
 use keep_alive::*;
+use rand::{seq::SliceRandom, thread_rng};
 use std::{
     collections::{HashMap, HashSet},
     convert::TryInto,
+    path::PathBuf,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 const CONSOLIDATE_CONNECTIONS: Duration = Duration::from_secs(60);
 const MAX_CONNECTIONS: usize = 12;
 const MIN_CONNECTIONS: usize = MAX_CONNECTIONS / 7 * 10;
+const SEED_NODE_MAX_CONNECTIONS: usize = 50;
+
+pub struct PeersConfig {
+    pub peers_file: PathBuf,
+    pub max_peer_age: Duration,
+    pub bans_file: PathBuf,
+    pub ban_period: Duration,
+    pub max_failed_pings: u32,
+    pub bundling: BundleConfig,
+    pub max_frame_size: usize,
+    pub seed_node: bool,
+}
 
 pub struct PeerInfo {
     reported_alive_at: SystemTime,
     gossiped_capabilities: Option<Vec<Capability>>,
     reported_capabilities: Option<Vec<Capability>>,
+    unreliable: bool,
 }
 impl From<(NodeAddress, &PeerInfo)> for Peer {
     fn from((addr, info): (NodeAddress, &PeerInfo)) -> Peer {
@@ -38,8 +57,7 @@ impl From<(NodeAddress, &PeerInfo)> for Peer {
             date: info
                 .reported_alive_at
                 .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_millis() as i64,
+                .map_or(0, |since| since.as_millis() as i64),
             supported_capabilities: info
                 .reported_capabilities
                 .as_ref()
@@ -55,11 +73,19 @@ pub struct Peers<D: SendableDispatcher> {
     broadcaster: Addr<Broadcaster>,
     network: BaseCurrencyNetwork,
     connections: HashMap<ConnectionId, Addr<Connection>>,
+    inbound_connections: HashSet<ConnectionId>,
     identified_connections: HashMap<ConnectionId, NodeAddress>,
     peer_infos: HashMap<NodeAddress, PeerInfo>,
     local_addr: Option<NodeAddress>,
     dispatcher: D,
-    proxy_port: Option<u16>,
+    transport: Arc<dyn Transport>,
+    reputation: Reputation,
+    capabilities: Vec<i32>,
+    seed_nodes: HashSet<NodeAddress>,
+    bootstrap_seeds: Vec<NodeAddress>,
+    max_connections: usize,
+    config: PeersConfig,
+    shutting_down: bool,
 }
 
 impl<D: SendableDispatcher> Peers<D> {
@@ -67,29 +93,44 @@ impl<D: SendableDispatcher> Peers<D> {
         network: BaseCurrencyNetwork,
         broadcaster: Addr<Broadcaster>,
         dispatcher: D,
-        proxy_port: Option<u16>,
+        transport: Arc<dyn Transport>,
+        config: PeersConfig,
     ) -> Addr<Self> {
         Self {
-            keep_alive: KeepAlive::start(),
+            keep_alive: KeepAlive::start(config.max_failed_pings),
             broadcaster: broadcaster.clone(),
             network,
             connections: HashMap::new(),
+            inbound_connections: HashSet::new(),
             identified_connections: HashMap::new(),
             peer_infos: HashMap::new(),
             local_addr: None,
             dispatcher,
-            proxy_port,
+            transport,
+            reputation: Reputation::load(config.bans_file.clone(), config.ban_period),
+            capabilities: local_capabilities(config.seed_node),
+            seed_nodes: seed_nodes(&network).into_iter().collect(),
+            bootstrap_seeds: Vec::new(),
+            max_connections: if config.seed_node {
+                SEED_NODE_MAX_CONNECTIONS
+            } else {
+                MAX_CONNECTIONS
+            },
+            config,
+            shutting_down: false,
         }
         .start()
     }
 
     fn get_dispatcher(&self, addr: Addr<Peers<D>>) -> impl SendableDispatcher {
-        dispatch::chain(self.dispatcher.clone())
-            .forward_to(ActorDispatcher::<KeepAlive, Ping>::new(
-                self.keep_alive.clone(),
-            ))
-            .forward_to(ActorDispatcher::<Self, GetPeersRequest>::new(addr.clone()))
-            .forward_to(ActorDispatcher::<Self, CloseConnectionMessage>::new(addr))
+        RateLimited::new(
+            dispatch::chain(self.dispatcher.clone())
+                .forward_to(ActorDispatcher::<KeepAlive, Ping>::new(
+                    self.keep_alive.clone(),
+                ))
+                .forward_to(ActorDispatcher::<Self, GetPeersRequest>::new(addr.clone()))
+                .forward_to(ActorDispatcher::<Self, CloseConnectionMessage>::new(addr)),
+        )
     }
 
     fn add_connection(
@@ -97,8 +138,10 @@ impl<D: SendableDispatcher> Peers<D> {
         id: ConnectionId,
         conn: Addr<Connection>,
         addr: Option<NodeAddress>,
+        ctx: &mut <Self as Actor>::Context,
     ) {
         info!("Adding {:?} @ {:?}", id, addr);
+        arbiter_spawn!(conn.send(ReportMisbehaviourTo(ctx.address().recipient())));
         let for_keep_alive = conn.downgrade();
         let for_broadcaster = conn.downgrade();
         self.connections.insert(id, conn);
@@ -114,6 +157,15 @@ impl<D: SendableDispatcher> Peers<D> {
             .send(event::ConnectionAdded(id, for_broadcaster)));
     }
 
+    fn enable_bundling_if_supported(&self, id: &ConnectionId, capabilities: &[i32]) {
+        if !capabilities.contains(&(Capability::BundleOfEnvelopes as i32)) {
+            return;
+        }
+        if let Some(conn) = self.connections.get(id) {
+            arbiter_spawn!(conn.send(EnableBundling(self.config.bundling)));
+        }
+    }
+
     fn update_peer_info(
         &mut self,
         addr: &NodeAddress,
@@ -121,6 +173,8 @@ impl<D: SendableDispatcher> Peers<D> {
         gossiped_capabilities: Option<Vec<i32>>,
         reported_capabilities: Option<Vec<i32>>,
     ) {
+        // A peer reported alive in the future would never be pruned
+        let reported_alive_at = reported_alive_at.min(SystemTime::now());
         let gossiped_capabilities = gossiped_capabilities
             .map(|c| c.into_iter().filter_map(|i| i.try_into().ok()).collect());
         let reported_capabilities = reported_capabilities
@@ -133,6 +187,8 @@ impl<D: SendableDispatcher> Peers<D> {
                 info.gossiped_capabilities = gossiped_capabilities
             }
             if reported_capabilities.is_some() {
+                // Only the peer itself reports its capabilities so it is answering again
+                info.unreliable = false;
                 info.reported_capabilities = reported_capabilities
             }
         } else {
@@ -142,13 +198,59 @@ impl<D: SendableDispatcher> Peers<D> {
                     reported_alive_at,
                     gossiped_capabilities: gossiped_capabilities,
                     reported_capabilities: reported_capabilities,
+                    unreliable: false,
                 },
             );
         }
     }
 
+    fn load_peer_infos(&mut self) {
+        if let Some(PeerList { peer }) = persistence::load::<PeerList>(&self.config.peers_file) {
+            info!(
+                "Loaded {} peers from {:?}",
+                peer.len(),
+                self.config.peers_file
+            );
+            self.add_to_peer_infos(peer);
+            self.prune_peer_infos();
+        }
+    }
+
+    fn persist_peer_infos(&self) {
+        let peer_list = PeerList {
+            peer: self
+                .peer_infos
+                .iter()
+                .map(|(addr, info)| (addr.clone(), info).into())
+                .collect(),
+        };
+        if let Err(e) = persistence::store(&self.config.peers_file, &peer_list) {
+            warn!("Couldn't persist peers: {:?}", e);
+        }
+    }
+
+    fn prune_peer_infos(&mut self) {
+        let max_age = self.config.max_peer_age;
+        let connected: HashSet<&NodeAddress> = self.identified_connections.values().collect();
+        self.peer_infos.retain(|addr, info| {
+            connected.contains(addr)
+                || info
+                    .reported_alive_at
+                    .elapsed()
+                    .map(|age| age < max_age)
+                    .unwrap_or(true)
+        });
+    }
+
     fn consolidate_connections(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if self.shutting_down {
+            return;
+        }
         info!("Consolidating peer connections");
+        self.log_connection_reports(ctx);
+        self.reputation.prune();
+        self.prune_peer_infos();
+        self.persist_peer_infos();
         let remove_ids: Vec<ConnectionId> = self
             .connections
             .iter()
@@ -163,6 +265,8 @@ impl<D: SendableDispatcher> Peers<D> {
             .collect();
         remove_ids.into_iter().for_each(|id| {
             self.connections.remove(&id);
+            self.inbound_connections.remove(&id);
+            self.reputation.forget_connection(&id);
             if self.identified_connections.remove(&id).is_none() {
                 self.drop_connection(&id, CloseConnectionReason::UnknownPeerAddress);
             }
@@ -178,8 +282,68 @@ impl<D: SendableDispatcher> Peers<D> {
             .then(|_, peers, ctx| fut::ok(peers.do_consolidate_connections(ctx)))
         }));
     }
+    fn connection_reports(
+        &self,
+    ) -> impl Future<Item = Vec<ConnectionReport>, Error = error::Error> {
+        let stats: Vec<_> = self
+            .connections
+            .iter()
+            .map(|(id, conn)| {
+                let id = *id;
+                let addr = self.identified_connections.get(&id).cloned();
+                let inbound = self.inbound_connections.contains(&id);
+                // A connection that is already gone just doesn't show up in the report
+                conn.send(GetStats).then(move |stats| {
+                    Ok::<_, error::Error>(stats.ok().map(|s| (id, addr, inbound, s)))
+                })
+            })
+            .collect();
+        self.keep_alive
+            .send(ReportRoundTripTimes)
+            .from_err::<error::Error>()
+            .join(future::join_all(stats))
+            .map(|(round_trip_times, stats)| {
+                stats
+                    .into_iter()
+                    .filter_map(|s| s)
+                    .map(|(id, addr, inbound, stats)| ConnectionReport {
+                        id,
+                        addr,
+                        inbound,
+                        round_trip_time: round_trip_times.get(&id).cloned(),
+                        stats,
+                    })
+                    .collect()
+            })
+    }
+    fn log_connection_reports(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.spawn(
+            fut::wrap_future(ctx.address().send(GetConnectionReports).flatten())
+                .map(|reports: Vec<ConnectionReport>, _: &mut Self, _| {
+                    reports.into_iter().for_each(|report| {
+                        debug!(
+                            "{:?} @ {:?} rtt: {:?}, age: {:?}, received: {} bytes {:?}, sent: {} bytes {:?}, retained: {}, dropped: {}, last received at: {:?}",
+                            report.id,
+                            report.addr,
+                            report.round_trip_time,
+                            report.stats.age,
+                            report.stats.bytes_received,
+                            report.stats.messages_received,
+                            report.stats.bytes_sent,
+                            report.stats.messages_sent,
+                            report.stats.retained_messages,
+                            report.stats.dropped_messages,
+                            report.stats.last_received_at,
+                        )
+                    })
+                })
+                .map_err(|e, _, _| warn!("Couldn't collect connection reports: {:?}", e)),
+        );
+    }
     fn drop_connection(&mut self, id: &ConnectionId, reason: CloseConnectionReason) {
+        self.reputation.forget_connection(id);
         self.identified_connections.remove(id);
+        self.inbound_connections.remove(id);
         if let Some(addr) = self.connections.remove(id) {
             if addr.connected() {
                 arbiter_spawn!(addr.send(Shutdown(reason)));
@@ -187,7 +351,13 @@ impl<D: SendableDispatcher> Peers<D> {
         }
     }
     fn new_connection_candidates(&self) -> HashSet<&NodeAddress> {
-        let mut candidates: HashSet<&NodeAddress> = self.peer_infos.keys().collect();
+        let mut candidates: HashSet<&NodeAddress> = self
+            .peer_infos
+            .keys()
+            .filter(|addr| {
+                !self.reputation.is_banned(addr) && self.local_addr.as_ref() != Some(*addr)
+            })
+            .collect();
         self.identified_connections.values().for_each(|v| {
             candidates.remove(&v);
         });
@@ -195,10 +365,18 @@ impl<D: SendableDispatcher> Peers<D> {
     }
 
     fn do_consolidate_connections(&mut self, ctx: &mut <Self as Actor>::Context) {
+        // A round that started before the shutdown mustn't redial closed connections
+        if self.shutting_down {
+            return;
+        }
         if self.identified_connections.len() < MIN_CONNECTIONS {
-            self.new_connection_candidates()
+            let candidates = by_reliability(
+                self.new_connection_candidates().into_iter().collect(),
+                &self.peer_infos,
+            );
+            candidates
                 .into_iter()
-                .take(MAX_CONNECTIONS - self.connections.len())
+                .take(self.max_connections.saturating_sub(self.connections.len()))
                 .cloned()
                 .for_each(|addr| {
                     ctx.spawn(
@@ -207,29 +385,55 @@ impl<D: SendableDispatcher> Peers<D> {
                                 addr.clone(),
                                 self.network.into(),
                                 self.get_dispatcher(ctx.address()),
-                                self.proxy_port,
+                                &*self.transport,
+                                self.config.max_frame_size,
                             )
                             .map_err(|_| ()),
                         )
                         .map(|(id, conn), peers: &mut Self, ctx| {
-                            peers.add_connection(id, conn, Some(addr));
+                            peers.add_connection(id, conn, Some(addr), ctx);
                             ctx.spawn(peers.request_peers_from(id));
                         }),
                     );
                 });
-        } else if self.connections.len() > MAX_CONNECTIONS {
-            let to_drop: Vec<ConnectionId> = self
+        } else if self.connections.len() > self.max_connections {
+            let excess = self.connections.len() - self.max_connections;
+            let (mut seeds, mut others): (Vec<ConnectionId>, Vec<ConnectionId>) = self
                 .connections
                 .keys()
-                .take(self.connections.len() - MAX_CONNECTIONS)
                 .cloned()
-                .collect();
-            to_drop.into_iter().for_each(|id| {
-                self.drop_connection(&id, CloseConnectionReason::TooManyConnectionsOpen)
-            });
+                .partition(|id| self.is_seed_connection(id));
+            // Seed nodes hold on to each other while regular nodes give up seeds first
+            let to_drop: Vec<(ConnectionId, CloseConnectionReason)> = if self.config.seed_node {
+                others
+                    .drain(..)
+                    .map(|id| (id, CloseConnectionReason::TooManyConnectionsOpen))
+                    .take(excess)
+                    .collect()
+            } else {
+                seeds
+                    .drain(..)
+                    .map(|id| (id, CloseConnectionReason::TooManySeedNodesConnected))
+                    .chain(
+                        others
+                            .drain(..)
+                            .map(|id| (id, CloseConnectionReason::TooManyConnectionsOpen)),
+                    )
+                    .take(excess)
+                    .collect()
+            };
+            to_drop
+                .into_iter()
+                .for_each(|(id, reason)| self.drop_connection(&id, reason));
         }
     }
 
+    fn is_seed_connection(&self, id: &ConnectionId) -> bool {
+        self.identified_connections
+            .get(id)
+            .map_or(false, |addr| self.seed_nodes.contains(addr))
+    }
+
     fn request_peers(&self) -> impl ActorFuture<Item = (), Error = (), Actor = Self> {
         let ids: Vec<ConnectionId> = self.connections.keys().cloned().collect();
         fut::wrap_stream(stream::iter_ok::<_, ()>(ids.into_iter()))
@@ -246,7 +450,7 @@ impl<D: SendableDispatcher> Peers<D> {
                 let request = GetPeersRequest {
                     sender_node_address: self.local_addr.clone(),
                     nonce: gen_nonce(),
-                    supported_capabilities: LOCAL_CAPABILITIES.clone(),
+                    supported_capabilities: self.capabilities.clone(),
                     reported_peers: self.peers_to_report(&id),
                 };
                 Either::A(
@@ -259,6 +463,7 @@ impl<D: SendableDispatcher> Peers<D> {
                                   },
                                   peers: &mut Peers<D>,
                                   _ctx| {
+                                peers.enable_bundling_if_supported(&id, &supported_capabilities);
                                 peers
                                     .identified_connections
                                     .get(&id)
@@ -296,23 +501,18 @@ impl<D: SendableDispatcher> Peers<D> {
             .collect()
     }
 
-    fn add_to_peer_infos(&mut self, mut reported: Vec<Peer>) {
-        reported.drain(..).for_each(
-            |Peer {
-                 node_address,
-                 date,
-                 supported_capabilities,
-             }| {
-                node_address.map(|addr| {
-                    self.update_peer_info(
-                        &addr,
-                        UNIX_EPOCH + Duration::from_millis(date as u64),
-                        Some(supported_capabilities),
-                        None,
-                    )
-                });
-            },
-        )
+    fn add_to_peer_infos(&mut self, reported: Vec<Peer>) {
+        reported
+            .into_iter()
+            .for_each(|peer| match (peer.reported_alive_at(), peer.node_address) {
+                (Some(reported_alive_at), Some(addr)) => self.update_peer_info(
+                    &addr,
+                    reported_alive_at,
+                    Some(peer.supported_capabilities),
+                    None,
+                ),
+                _ => debug!("Ignoring invalid reported peer"),
+            })
     }
 
     fn update_alive_times(&self) -> impl ActorFuture<Item = (), Error = (), Actor = Self> {
@@ -330,9 +530,29 @@ impl<D: SendableDispatcher> Peers<D> {
             .map_err(|_, _, _| ())
     }
 }
+
+// Peers that stopped answering pings are only retried when nothing else is left
+fn by_reliability<'a>(
+    mut candidates: Vec<&'a NodeAddress>,
+    peer_infos: &HashMap<NodeAddress, PeerInfo>,
+) -> Vec<&'a NodeAddress> {
+    candidates.sort_by_key(|addr| peer_infos.get(*addr).map_or(false, |i| i.unreliable));
+    candidates
+}
+
 impl<D: SendableDispatcher> Actor for Peers<D> {
     type Context = Context<Peers<D>>;
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.keep_alive
+            .do_send(ReportUnresponsiveTo(ctx.address().recipient()));
+        self.load_peer_infos();
+        if self.config.seed_node {
+            let now = SystemTime::now();
+            let seeds: Vec<NodeAddress> = self.seed_nodes.iter().cloned().collect();
+            seeds
+                .iter()
+                .for_each(|addr| self.update_peer_info(addr, now, None, None));
+        }
         ctx.run_interval(CONSOLIDATE_CONNECTIONS, |peers, ctx| {
             peers.consolidate_connections(ctx);
         });
@@ -351,7 +571,8 @@ impl<D: SendableDispatcher> Handler<SeedConnection> for super::Peers<D> {
         ctx: &mut Self::Context,
     ) -> Self::Result {
         arbiter_spawn!(connection.send(SetDispatcher(self.get_dispatcher(ctx.address()))));
-        self.add_connection(id, connection, Some(addr));
+        self.bootstrap_seeds.push(addr.clone());
+        self.add_connection(id, connection, Some(addr), ctx);
         self.consolidate_connections(ctx);
     }
 }
@@ -371,7 +592,15 @@ impl<D: SendableDispatcher> Handler<Receive<GetPeersRequest>> for Peers<D> {
         ): Receive<GetPeersRequest>,
         ctx: &mut Self::Context,
     ) -> Self::Result {
+        if let Some(addr) = sender_node_address.as_ref() {
+            if self.reputation.is_banned(addr) {
+                info!("Rejecting banned peer {:?}", addr);
+                self.drop_connection(&conn_id, CloseConnectionReason::PeerBanned);
+                return;
+            }
+        }
         self.add_to_peer_infos(reported_peers);
+        self.enable_bundling_if_supported(&conn_id, &supported_capabilities);
         if let Some(addr) = sender_node_address {
             self.update_peer_info(&addr, SystemTime::now(), None, Some(supported_capabilities));
             self.identified_connections.insert(conn_id, addr);
@@ -381,7 +610,7 @@ impl<D: SendableDispatcher> Handler<Receive<GetPeersRequest>> for Peers<D> {
                 let res = GetPeersResponse {
                     request_nonce: nonce,
                     reported_peers: peers.peers_to_report(&conn_id),
-                    supported_capabilities: constants::LOCAL_CAPABILITIES.clone(),
+                    supported_capabilities: peers.capabilities.clone(),
                 };
                 fut::wrap_future(conn.send(Payload(res)).then(|_| Ok(())))
             }));
@@ -404,9 +633,12 @@ impl<D: SendableDispatcher> Handler<ServerStarted> for Peers<D> {
     fn handle(
         &mut self,
         ServerStarted(addr): ServerStarted,
-        _: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> Self::Result {
         self.local_addr = Some(addr);
+        if !self.peer_infos.is_empty() {
+            self.consolidate_connections(ctx);
+        }
     }
 }
 
@@ -415,12 +647,170 @@ impl<D: SendableDispatcher> Handler<IncomingConnection> for Peers<D> {
 
     fn handle(
         &mut self,
-        IncomingConnection(tcp): IncomingConnection,
+        IncomingConnection(stream): IncomingConnection,
         ctx: &mut Self::Context,
     ) -> Self::Result {
         let dispatcher = self.get_dispatcher(ctx.address());
-        let (id, conn) = Connection::from_tcp_stream(tcp, self.network.into(), dispatcher);
-        self.add_connection(id, conn, None);
+        let (id, conn) = Connection::from_stream(
+            stream,
+            self.network.into(),
+            dispatcher,
+            self.config.max_frame_size,
+        );
+        self.inbound_connections.insert(id);
+        self.add_connection(id, conn, None, ctx);
+    }
+}
+
+impl<D: SendableDispatcher> Handler<Misbehaviour> for Peers<D> {
+    type Result = ();
+    fn handle(
+        &mut self,
+        Misbehaviour(conn_id, offence): Misbehaviour,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        warn!("{:?} misbehaved: {:?}", conn_id, offence);
+        // An inbound peer can claim any address, so only addresses we dialled get banned
+        let dialled_addr = if self.inbound_connections.contains(&conn_id) {
+            None
+        } else {
+            self.identified_connections.get(&conn_id).map(Clone::clone)
+        };
+        match dialled_addr {
+            Some(addr) => {
+                if self.reputation.penalize(&addr, offence) {
+                    self.drop_connection(&conn_id, offence.into());
+                }
+            }
+            None => {
+                if self.reputation.penalize_connection(conn_id, offence) {
+                    self.drop_connection(&conn_id, offence.into());
+                }
+            }
+        }
+    }
+}
+
+impl<D: SendableDispatcher> Handler<ShuttingDown> for Peers<D> {
+    type Result = ();
+    fn handle(&mut self, _: ShuttingDown, _ctx: &mut Self::Context) {
+        self.shutting_down = true;
+        self.persist_peer_infos();
+    }
+}
+
+impl<D: SendableDispatcher> Handler<Unresponsive> for Peers<D> {
+    type Result = ();
+    fn handle(&mut self, Unresponsive(conn_id): Unresponsive, _ctx: &mut Self::Context) {
+        info!("{:?} stopped answering pings", conn_id);
+        if let Some(info) = self
+            .identified_connections
+            .get(&conn_id)
+            .and_then(|addr| self.peer_infos.get_mut(addr))
+        {
+            info.unreliable = true;
+        }
+        self.drop_connection(&conn_id, CloseConnectionReason::SocketTimeout);
+    }
+}
+
+pub struct RequestUpdatedData(pub Vec<Vec<u8>>);
+impl Message for RequestUpdatedData {
+    type Result = Result<(ConnectionId, GetDataResponse), error::Error>;
+}
+impl<D: SendableDispatcher> Handler<RequestUpdatedData> for Peers<D> {
+    type Result = Box<dyn Future<Item = (ConnectionId, GetDataResponse), Error = error::Error>>;
+    fn handle(
+        &mut self,
+        RequestUpdatedData(excluded_keys): RequestUpdatedData,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let ids: Vec<&ConnectionId> = self.identified_connections.keys().collect();
+        let (id, conn) = match ids
+            .choose(&mut thread_rng())
+            .and_then(|id| Some((**id, self.connections.get(id)?.clone())))
+        {
+            Some(target) => target,
+            None => return Box::new(future::err(error::Error::NoPeerConnected)),
+        };
+        let request = GetUpdatedDataRequest {
+            sender_node_address: self.local_addr.clone(),
+            nonce: gen_nonce(),
+            excluded_keys,
+        };
+        Box::new(
+            conn.send(Request(request))
+                .flatten()
+                .map(move |response| (id, response)),
+        )
+    }
+}
+
+pub struct ConnectionReport {
+    pub id: ConnectionId,
+    pub addr: Option<NodeAddress>,
+    pub inbound: bool,
+    pub round_trip_time: Option<Duration>,
+    pub stats: ConnectionStats,
+}
+pub struct GetConnectionReports;
+impl Message for GetConnectionReports {
+    type Result = Result<Vec<ConnectionReport>, error::Error>;
+}
+impl<D: SendableDispatcher> Handler<GetConnectionReports> for Peers<D> {
+    type Result = Box<dyn Future<Item = Vec<ConnectionReport>, Error = error::Error>>;
+    fn handle(&mut self, _: GetConnectionReports, _ctx: &mut Self::Context) -> Self::Result {
+        Box::new(self.connection_reports())
+    }
+}
+
+pub struct KnownPeer {
+    pub addr: NodeAddress,
+    pub reported_alive_at: SystemTime,
+    pub gossiped_capabilities: Option<Vec<Capability>>,
+    pub reported_capabilities: Option<Vec<Capability>>,
+}
+pub struct NetworkInfo {
+    pub local_addr: Option<NodeAddress>,
+    pub bootstrap_seeds: Vec<NodeAddress>,
+    pub connections: Vec<ConnectionReport>,
+    pub peers: Vec<KnownPeer>,
+}
+pub struct GetNetworkInfo;
+impl Message for GetNetworkInfo {
+    type Result = Result<NetworkInfo, error::Error>;
+}
+impl<D: SendableDispatcher> Handler<GetNetworkInfo> for Peers<D> {
+    type Result = Box<dyn Future<Item = NetworkInfo, Error = error::Error>>;
+    fn handle(&mut self, _: GetNetworkInfo, _ctx: &mut Self::Context) -> Self::Result {
+        let local_addr = self.local_addr.clone();
+        let bootstrap_seeds = self.bootstrap_seeds.clone();
+        let peers = self
+            .peer_infos
+            .iter()
+            .map(|(addr, info)| KnownPeer {
+                addr: addr.clone(),
+                reported_alive_at: info.reported_alive_at,
+                gossiped_capabilities: info.gossiped_capabilities.clone(),
+                reported_capabilities: info.reported_capabilities.clone(),
+            })
+            .collect();
+        Box::new(
+            self.connection_reports()
+                .map(move |connections| NetworkInfo {
+                    local_addr,
+                    bootstrap_seeds,
+                    connections,
+                    peers,
+                }),
+        )
+    }
+}
+
+impl<D: SendableDispatcher> Handler<GetBannedPeers> for Peers<D> {
+    type Result = MessageResult<GetBannedPeers>;
+    fn handle(&mut self, _: GetBannedPeers, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.reputation.banned_peers())
     }
 }
 
@@ -433,3 +823,182 @@ pub mod event {
         type Result = ();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::{
+        dispatch::{Dispatch, Dispatcher},
+        transport::Memory,
+    };
+    use std::{env, fs, path::Path, time::Instant};
+    use tokio::timer::Delay;
+
+    #[derive(Clone)]
+    struct Ignore;
+    impl Dispatcher for Ignore {
+        fn dispatch(&self, _: ConnectionId, _: network_envelope::Message) -> Dispatch {
+            Dispatch::Consumed
+        }
+    }
+
+    fn addr(port: i32) -> NodeAddress {
+        NodeAddress {
+            host_name: "peer.onion".to_string(),
+            port,
+        }
+    }
+
+    fn info(unreliable: bool) -> PeerInfo {
+        PeerInfo {
+            reported_alive_at: SystemTime::now(),
+            gossiped_capabilities: None,
+            reported_capabilities: None,
+            unreliable,
+        }
+    }
+
+    #[test]
+    fn unreliable_candidates_last() {
+        let mut peer_infos = HashMap::new();
+        peer_infos.insert(addr(1), info(true));
+        peer_infos.insert(addr(2), info(false));
+        peer_infos.insert(addr(3), info(true));
+        let (first, second, third, unknown) = (addr(1), addr(2), addr(3), addr(4));
+        let ordered = by_reliability(vec![&first, &second, &third, &unknown], &peer_infos);
+        assert_eq!(ordered, vec![&second, &unknown, &first, &third]);
+    }
+
+    fn millis(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+    }
+
+    fn reported(port: i32, alive_at: SystemTime) -> Peer {
+        Peer {
+            node_address: Some(addr(port)),
+            date: millis(alive_at),
+            supported_capabilities: Vec::new(),
+        }
+    }
+
+    fn start_peers(dir: &Path, max_peer_age: Duration) -> Addr<Peers<Ignore>> {
+        Peers::start(
+            BaseCurrencyNetwork::BtcRegtest,
+            Broadcaster::start(),
+            Ignore,
+            Arc::new(Memory::new()),
+            PeersConfig {
+                peers_file: dir.join("peers"),
+                max_peer_age,
+                bans_file: dir.join("bans"),
+                ban_period: Duration::from_secs(60 * 60),
+                max_failed_pings: 3,
+                bundling: BundleConfig {
+                    window: Duration::from_millis(50),
+                    max_size: 100,
+                },
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+                seed_node: false,
+            },
+        )
+    }
+
+    #[test]
+    fn prunes_and_persists_peers() {
+        let mut sys = System::new("peers");
+        let dir = env::temp_dir().join(format!("risq-peers-{}", gen_nonce()));
+        fs::create_dir_all(&dir).unwrap();
+        let max_peer_age = Duration::from_secs(60 * 60);
+        let now = SystemTime::now();
+        let recent = now - Duration::from_secs(60);
+        persistence::store(
+            &dir.join("peers"),
+            &PeerList {
+                peer: vec![
+                    reported(1, recent),
+                    reported(2, now - max_peer_age * 2),
+                    reported(3, now + max_peer_age * 10),
+                ],
+            },
+        )
+        .unwrap();
+
+        let peers = start_peers(&dir, max_peer_age);
+        let info = sys.block_on(peers.send(GetNetworkInfo)).unwrap().unwrap();
+        let mut known: Vec<i32> = info.peers.iter().map(|peer| peer.addr.port).collect();
+        known.sort();
+        assert_eq!(known, vec![1, 3]);
+        // The future date is clamped so the peer ages out like any other
+        assert!(info
+            .peers
+            .iter()
+            .all(|peer| peer.reported_alive_at <= SystemTime::now()));
+
+        sys.block_on(peers.send(ShuttingDown)).unwrap();
+        let PeerList { mut peer } = persistence::load(&dir.join("peers")).unwrap();
+        peer.sort_by_key(|peer| peer.node_address.as_ref().map(|addr| addr.port));
+        assert_eq!(peer.len(), 2);
+        assert_eq!(peer[0], reported(1, recent));
+        assert!(peer[1].date <= millis(SystemTime::now()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn spoofed_inbound_address_is_not_banned() {
+        let mut sys = System::new("spoofing");
+        let dir = env::temp_dir().join(format!("risq-spoofing-{}", gen_nonce()));
+        fs::create_dir_all(&dir).unwrap();
+        let memory = Memory::new();
+        let local = addr(9000);
+        let incoming = memory.listen(local.clone());
+        let peers = start_peers(&dir, Duration::from_secs(60 * 60));
+        let accept = peers.clone();
+        Arbiter::spawn(
+            incoming
+                .map_err(|_| ())
+                .for_each(move |stream| accept.send(IncomingConnection(stream)).map_err(|_| ())),
+        );
+        let (_, attacker) = sys
+            .block_on(Connection::open(
+                local,
+                BaseCurrencyNetwork::BtcRegtest.into(),
+                Ignore,
+                &memory,
+                DEFAULT_MAX_FRAME_SIZE,
+            ))
+            .unwrap();
+
+        // Claims to be an honest peer before misbehaving
+        let victim = addr(1);
+        sys.block_on(
+            attacker
+                .send(Payload(GetPeersRequest {
+                    sender_node_address: Some(victim.clone()),
+                    nonce: 1,
+                    ..Default::default()
+                }))
+                .flatten(),
+        )
+        .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let conn_id = loop {
+            let info = sys.block_on(peers.send(GetNetworkInfo)).unwrap().unwrap();
+            if let Some(report) = info
+                .connections
+                .iter()
+                .find(|report| report.inbound && report.addr.as_ref() == Some(&victim))
+            {
+                break report.id;
+            }
+            assert!(Instant::now() < deadline, "Inbound peer wasn't identified");
+            sys.block_on(Delay::new(Instant::now() + Duration::from_millis(10)))
+                .unwrap();
+        };
+
+        peers.do_send(Misbehaviour(conn_id, Offence::CorruptedData));
+        assert!(sys.block_on(peers.send(GetBannedPeers)).unwrap().is_empty());
+        let info = sys.block_on(peers.send(GetNetworkInfo)).unwrap().unwrap();
+        assert!(info.connections.iter().all(|report| report.id != conn_id));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
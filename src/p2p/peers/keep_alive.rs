@@ -11,7 +11,7 @@ use lazy_static::lazy_static;
 use rand::{thread_rng, Rng};
 use std::{
     collections::HashMap,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 lazy_static! {
@@ -27,15 +27,36 @@ struct Info {
 pub struct KeepAlive {
     infos: HashMap<ConnectionId, Info>,
     connections: HashMap<ConnectionId, WeakAddr<Connection>>,
+    failed_pings: HashMap<ConnectionId, u32>,
+    max_failed_pings: u32,
+    unresponsive: Option<Recipient<Unresponsive>>,
 }
 impl KeepAlive {
-    pub fn start() -> Addr<KeepAlive> {
+    pub fn start(max_failed_pings: u32) -> Addr<KeepAlive> {
         KeepAlive {
             infos: HashMap::new(),
             connections: HashMap::new(),
+            failed_pings: HashMap::new(),
+            max_failed_pings,
+            unresponsive: None,
         }
         .start()
     }
+
+    fn ping_failed(&mut self, id: ConnectionId) {
+        let failed = self.failed_pings.entry(id).or_insert(0);
+        *failed += 1;
+        debug!("{:?} didn't answer {} ping(s) in a row", id, failed);
+        if *failed < self.max_failed_pings {
+            return;
+        }
+        self.failed_pings.remove(&id);
+        self.infos.remove(&id);
+        self.connections.remove(&id);
+        if let Some(unresponsive) = self.unresponsive.as_ref() {
+            let _ = unresponsive.do_send(Unresponsive(id));
+        }
+    }
 }
 impl Actor for KeepAlive {
     type Context = Context<KeepAlive>;
@@ -50,7 +71,11 @@ impl Actor for KeepAlive {
                     infos.remove(id);
                     false
                 }
-            })
+            });
+            let connections = &keep_alive.connections;
+            keep_alive
+                .failed_pings
+                .retain(|id, _| connections.contains_key(id));
         });
     }
 }
@@ -64,6 +89,24 @@ impl Handler<ConnectionAdded> for KeepAlive {
         self.connections.insert(id, conn);
     }
 }
+pub struct Unresponsive(pub ConnectionId);
+impl Message for Unresponsive {
+    type Result = ();
+}
+pub struct ReportUnresponsiveTo(pub Recipient<Unresponsive>);
+impl Message for ReportUnresponsiveTo {
+    type Result = ();
+}
+impl Handler<ReportUnresponsiveTo> for KeepAlive {
+    type Result = ();
+    fn handle(
+        &mut self,
+        ReportUnresponsiveTo(recipient): ReportUnresponsiveTo,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        self.unresponsive = Some(recipient);
+    }
+}
 pub struct ReportLastActive;
 impl Message for ReportLastActive {
     type Result = HashMap<ConnectionId, SystemTime>;
@@ -80,11 +123,28 @@ impl Handler<ReportLastActive> for KeepAlive {
         )
     }
 }
+pub struct ReportRoundTripTimes;
+impl Message for ReportRoundTripTimes {
+    type Result = HashMap<ConnectionId, Duration>;
+}
+impl Handler<ReportRoundTripTimes> for KeepAlive {
+    type Result = MessageResult<ReportRoundTripTimes>;
+
+    fn handle(&mut self, _: ReportRoundTripTimes, _: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.infos
+                .iter()
+                .map(|(id, info)| (*id, info.last_round_trip_time))
+                .collect(),
+        )
+    }
+}
 
 impl Handler<Receive<Ping>> for KeepAlive {
     type Result = ();
     fn handle(&mut self, Receive(id, ping): Receive<Ping>, _: &mut Self::Context) -> Self::Result {
         let now = SystemTime::now();
+        self.failed_pings.remove(&id);
         self.infos.insert(
             id,
             Info {
@@ -108,10 +168,9 @@ fn ping_peer(
     info: Option<&Info>,
     ctx: &mut Context<KeepAlive>,
 ) -> bool {
-    let send_time = SystemTime::now();
     let should_ping = match info {
         Some(info)
-            if send_time
+            if SystemTime::now()
                 .duration_since(info.last_active)
                 .map(|t| t > *LAST_ACTIVITY_AGE)
                 .unwrap_or(false) =>
@@ -127,20 +186,23 @@ fn ping_peer(
                 nonce: gen_nonce(),
                 last_round_trip_time: info.map_or(0, |i| i.last_round_trip_time.as_millis() as i32),
             };
+            // Monotonic so a wall clock adjustment can't distort the round trip time
+            let send_time = Instant::now();
             ctx.spawn(
-                fut::wrap_future(conn.send(Request(ping)).flatten().map(move |_pong| {
-                    let ret = SystemTime::now();
-                    Info {
-                        last_active: ret,
-                        last_round_trip_time: ret
-                            .duration_since(send_time)
-                            .expect("Pong before Ping"),
-                    }
+                fut::wrap_future(conn.send(Request(ping)).flatten().map(move |_pong| Info {
+                    last_active: SystemTime::now(),
+                    last_round_trip_time: send_time.elapsed(),
                 }))
-                .map(move |info, keep_alive: &mut KeepAlive, _ctx| {
-                    keep_alive.infos.insert(id, info)
-                })
-                .then(|_, _, _| fut::ok(())),
+                .then(move |res, keep_alive: &mut KeepAlive, _ctx| {
+                    match res {
+                        Ok(info) => {
+                            keep_alive.failed_pings.remove(&id);
+                            keep_alive.infos.insert(id, info);
+                        }
+                        Err(_) => keep_alive.ping_failed(id),
+                    }
+                    fut::ok(())
+                }),
             );
             true
         } else {
@@ -150,3 +212,61 @@ fn ping_peer(
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[derive(Default)]
+    struct Evictions(Vec<ConnectionId>);
+    impl Actor for Evictions {
+        type Context = Context<Self>;
+    }
+    impl Handler<Unresponsive> for Evictions {
+        type Result = ();
+        fn handle(&mut self, Unresponsive(id): Unresponsive, _: &mut Self::Context) {
+            self.0.push(id);
+        }
+    }
+    struct GetEvictions;
+    impl Message for GetEvictions {
+        type Result = Vec<ConnectionId>;
+    }
+    impl Handler<GetEvictions> for Evictions {
+        type Result = MessageResult<GetEvictions>;
+        fn handle(&mut self, _: GetEvictions, _: &mut Self::Context) -> Self::Result {
+            MessageResult(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn evicts_after_max_failed_pings() {
+        let mut sys = System::new("keep-alive");
+        let evictions = Evictions::default().start();
+        let mut keep_alive = KeepAlive {
+            infos: HashMap::new(),
+            connections: HashMap::new(),
+            failed_pings: HashMap::new(),
+            max_failed_pings: 5,
+            unresponsive: Some(evictions.clone().recipient()),
+        };
+        let id = ConnectionId::try_from(&[1u8; 16][..]).unwrap();
+        let other = ConnectionId::try_from(&[2u8; 16][..]).unwrap();
+        for _ in 0..4 {
+            keep_alive.ping_failed(id);
+        }
+        keep_alive.ping_failed(other);
+        assert!(sys
+            .block_on(evictions.send(GetEvictions))
+            .unwrap()
+            .is_empty());
+        keep_alive.ping_failed(id);
+        assert_eq!(
+            sys.block_on(evictions.send(GetEvictions)).unwrap(),
+            vec![id]
+        );
+        assert!(!keep_alive.failed_pings.contains_key(&id));
+        assert_eq!(keep_alive.failed_pings.get(&other), Some(&1));
+    }
+}
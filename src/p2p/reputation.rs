@@ -0,0 +1,273 @@
+use super::connection::ConnectionId;
+use crate::{
+    bisq::{constants::CloseConnectionReason, payload::NodeAddress, persistence},
+    prelude::*,
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const BAN_THRESHOLD: u32 = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Offence {
+    InvalidSignature,
+    CorruptedData,
+    UnsolicitedResponse,
+    Flooding,
+}
+impl Offence {
+    fn penalty(self) -> u32 {
+        match self {
+            Offence::InvalidSignature => 25,
+            Offence::CorruptedData => BAN_THRESHOLD,
+            Offence::UnsolicitedResponse => 10,
+            Offence::Flooding => 50,
+        }
+    }
+}
+impl From<Offence> for CloseConnectionReason {
+    fn from(offence: Offence) -> CloseConnectionReason {
+        match offence {
+            Offence::CorruptedData => CloseConnectionReason::CorruptedData,
+            _ => CloseConnectionReason::RuleViolation,
+        }
+    }
+}
+
+pub struct Misbehaviour(pub ConnectionId, pub Offence);
+impl Message for Misbehaviour {
+    type Result = ();
+}
+pub struct ReportMisbehaviourTo(pub Recipient<Misbehaviour>);
+impl Message for ReportMisbehaviourTo {
+    type Result = ();
+}
+
+#[derive(Clone)]
+pub struct BannedPeer {
+    pub node_address: NodeAddress,
+    pub banned_until: SystemTime,
+}
+pub struct GetBannedPeers;
+impl Message for GetBannedPeers {
+    type Result = Vec<BannedPeer>;
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PersistedBan {
+    #[prost(string, tag = "1")]
+    host_name: String,
+    #[prost(int32, tag = "2")]
+    port: i32,
+    #[prost(uint64, tag = "3")]
+    banned_until_secs: u64,
+}
+#[derive(Clone, PartialEq, prost::Message)]
+struct BanList {
+    #[prost(message, repeated, tag = "1")]
+    ban: Vec<PersistedBan>,
+}
+
+pub struct Reputation {
+    scores: HashMap<NodeAddress, u32>,
+    connection_scores: HashMap<ConnectionId, u32>,
+    bans: HashMap<NodeAddress, SystemTime>,
+    ban_period: Duration,
+    bans_file: PathBuf,
+}
+impl Reputation {
+    pub fn load(bans_file: PathBuf, ban_period: Duration) -> Reputation {
+        let bans = persistence::load::<BanList>(&bans_file)
+            .map(|list| list.ban)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(
+                |PersistedBan {
+                     host_name,
+                     port,
+                     banned_until_secs,
+                 }| {
+                    Some((
+                        NodeAddress { host_name, port },
+                        UNIX_EPOCH.checked_add(Duration::from_secs(banned_until_secs))?,
+                    ))
+                },
+            )
+            .collect();
+        let mut reputation = Reputation {
+            scores: HashMap::new(),
+            connection_scores: HashMap::new(),
+            bans,
+            ban_period,
+            bans_file,
+        };
+        reputation.prune();
+        reputation
+    }
+
+    /// Returns true if the offence got the peer banned
+    pub fn penalize(&mut self, addr: &NodeAddress, offence: Offence) -> bool {
+        let score = self.scores.entry(addr.clone()).or_insert(0);
+        *score += offence.penalty();
+        if *score < BAN_THRESHOLD {
+            return false;
+        }
+        warn!("Banning {:?} for {:?}", addr, self.ban_period);
+        self.scores.remove(addr);
+        self.bans
+            .insert(addr.clone(), SystemTime::now() + self.ban_period);
+        self.persist();
+        true
+    }
+
+    /// Connections without an address can't be banned, returns true once they should be dropped
+    pub fn penalize_connection(&mut self, id: ConnectionId, offence: Offence) -> bool {
+        let score = self.connection_scores.entry(id).or_insert(0);
+        *score += offence.penalty();
+        *score >= BAN_THRESHOLD
+    }
+
+    pub fn forget_connection(&mut self, id: &ConnectionId) {
+        self.connection_scores.remove(id);
+    }
+
+    pub fn is_banned(&self, addr: &NodeAddress) -> bool {
+        self.bans
+            .get(addr)
+            .map(|until| until.elapsed().is_err())
+            .unwrap_or(false)
+    }
+
+    pub fn prune(&mut self) {
+        self.scores.values_mut().for_each(|score| *score /= 2);
+        self.scores.retain(|_, score| *score > 0);
+        self.connection_scores
+            .values_mut()
+            .for_each(|score| *score /= 2);
+        self.connection_scores.retain(|_, score| *score > 0);
+        let n_bans = self.bans.len();
+        self.bans.retain(|_, until| until.elapsed().is_err());
+        if self.bans.len() != n_bans {
+            self.persist();
+        }
+    }
+
+    pub fn banned_peers(&self) -> Vec<BannedPeer> {
+        self.bans
+            .iter()
+            .map(|(addr, until)| BannedPeer {
+                node_address: addr.clone(),
+                banned_until: *until,
+            })
+            .collect()
+    }
+
+    fn persist(&self) {
+        let ban_list = BanList {
+            ban: self
+                .bans
+                .iter()
+                .map(|(addr, until)| PersistedBan {
+                    host_name: addr.host_name.clone(),
+                    port: addr.port,
+                    banned_until_secs: until
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |since| since.as_secs()),
+                })
+                .collect(),
+        };
+        if let Err(e) = persistence::store(&self.bans_file, &ban_list) {
+            warn!("Couldn't persist banned peers: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bisq::payload::gen_nonce;
+    use std::{convert::TryFrom, env, fs};
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn bans_file() -> PathBuf {
+        env::temp_dir().join(format!("risq-bans-{}", gen_nonce()))
+    }
+
+    fn peer() -> NodeAddress {
+        NodeAddress {
+            host_name: "peer.onion".to_string(),
+            port: 9999,
+        }
+    }
+
+    #[test]
+    fn bans_at_threshold() {
+        let path = bans_file();
+        let mut reputation = Reputation::load(path.clone(), HOUR);
+        for _ in 0..3 {
+            assert!(!reputation.penalize(&peer(), Offence::InvalidSignature));
+        }
+        assert!(!reputation.is_banned(&peer()));
+        assert!(reputation.penalize(&peer(), Offence::InvalidSignature));
+        assert!(reputation.is_banned(&peer()));
+        assert_eq!(reputation.banned_peers().len(), 1);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn scores_decay() {
+        let path = bans_file();
+        let mut reputation = Reputation::load(path.clone(), HOUR);
+        reputation.penalize(&peer(), Offence::Flooding);
+        reputation.penalize(&peer(), Offence::InvalidSignature);
+        reputation.prune();
+        assert!(!reputation.penalize(&peer(), Offence::Flooding));
+        assert!(!reputation.is_banned(&peer()));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn bans_expire() {
+        let path = bans_file();
+        let mut reputation = Reputation::load(path.clone(), Duration::from_secs(0));
+        assert!(reputation.penalize(&peer(), Offence::CorruptedData));
+        assert!(!reputation.is_banned(&peer()));
+        reputation.prune();
+        assert!(reputation.banned_peers().is_empty());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn persists_bans() {
+        let path = bans_file();
+        let mut reputation = Reputation::load(path.clone(), HOUR);
+        reputation.penalize(&peer(), Offence::CorruptedData);
+        let reloaded = Reputation::load(path.clone(), HOUR);
+        assert!(reloaded.is_banned(&peer()));
+
+        let mut expired = Reputation::load(path.clone(), Duration::from_secs(0));
+        expired.bans.insert(peer(), UNIX_EPOCH);
+        expired.persist();
+        assert!(Reputation::load(path.clone(), HOUR)
+            .banned_peers()
+            .is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn scores_unidentified_connections() {
+        let path = bans_file();
+        let mut reputation = Reputation::load(path.clone(), HOUR);
+        let id = ConnectionId::try_from(&[1u8; 16][..]).unwrap();
+        assert!(!reputation.penalize_connection(id, Offence::UnsolicitedResponse));
+        assert!(reputation.penalize_connection(id, Offence::CorruptedData));
+        reputation.forget_connection(&id);
+        assert!(!reputation.penalize_connection(id, Offence::UnsolicitedResponse));
+        assert!(reputation.banned_peers().is_empty());
+        assert!(!path.exists());
+    }
+}
@@ -2,7 +2,7 @@ use super::{
     bootstrap::Bootstrap,
     dispatch::SendableDispatcher,
     peers::Peers,
-    tor::{AddOnionConfig, TorControl},
+    tor::{AddOnionConfig, OnionVersion, TorControl},
 };
 use crate::{
     bisq::payload::NodeAddress,
@@ -13,61 +13,95 @@ use crate::{
 };
 use std::{
     io,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
 };
 
 pub struct TorConfig {
     pub hidden_service_port: u16,
+    pub tc_host: String,
     pub tc_port: u16,
+    // Where tor forwards hidden service connections to when the p2p port isn't on tor's host
+    pub onion_target: Option<String>,
     pub private_key_path: PathBuf,
+    pub onion_version: OnionVersion,
+    pub control_password: Option<String>,
 }
 
 pub struct Server<D: SendableDispatcher> {
+    listen_addr: IpAddr,
     listen_port: u16,
+    advertised_addr: Option<String>,
     tor_conf: Option<TorConfig>,
+    // The hidden service lives as long as the control connection it was added on
+    onion: Option<(TorControl, String)>,
     peers: Addr<Peers<D>>,
     bootstrap: Addr<Bootstrap<D>>,
 }
 pub fn start<D: SendableDispatcher>(
+    listen_addr: IpAddr,
     listen_port: u16,
+    advertised_addr: Option<String>,
     peers: Addr<Peers<D>>,
     bootstrap: Addr<Bootstrap<D>>,
     tor_conf: Option<TorConfig>,
 ) -> Addr<Server<D>> {
     Server {
+        listen_addr,
         listen_port,
+        advertised_addr,
         tor_conf,
+        onion: None,
         peers,
         bootstrap,
     }
     .start()
 }
+impl<D: SendableDispatcher> Server<D> {
+    fn remove_onion(&mut self) {
+        if let Some((mut tc, service_id)) = self.onion.take() {
+            info!("Removing hidden service {}", service_id);
+            if let Err(e) = tc.del_onion(&service_id) {
+                warn!("Couldn't remove hidden service: {:?}", e);
+            }
+        }
+    }
+}
 impl<D: SendableDispatcher> Actor for Server<D> {
     type Context = Context<Server<D>>;
     fn started(&mut self, ctx: &mut Self::Context) {
-        let listen_socket =
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), self.listen_port);
+        let listen_socket = SocketAddr::new(self.listen_addr, self.listen_port);
         let tcp = TcpListener::bind(&listen_socket).expect("Unable to bind port");
         ctx.add_stream(tcp.incoming());
         let addr = match &self.tor_conf {
             Some(tor_conf) => {
-                let mut tc = TorControl::connect(("localhost", tor_conf.tc_port))
-                    .expect("Couldn't authenticate to TorControl");
+                let mut tc = TorControl::connect(
+                    (tor_conf.tc_host.as_str(), tor_conf.tc_port),
+                    tor_conf.control_password.as_ref().map(String::as_str),
+                )
+                .expect("Couldn't authenticate to TorControl");
                 let onion_addr = tc
-                    .add_v2_onion(AddOnionConfig {
+                    .add_onion(AddOnionConfig {
                         virtual_port: tor_conf.hidden_service_port,
-                        target_port: self.listen_port,
+                        target: tor_conf
+                            .onion_target
+                            .clone()
+                            .unwrap_or_else(|| self.listen_port.to_string()),
                         private_key_path: tor_conf.private_key_path.clone(),
+                        version: tor_conf.onion_version,
                     })
                     .expect("Couldn't create hidden service");
+                self.onion = Some((tc, onion_addr.service_id));
                 NodeAddress {
                     host_name: onion_addr.onion_service,
                     port: onion_addr.port as i32,
                 }
             }
             None => NodeAddress {
-                host_name: "localhost".to_string(),
+                host_name: self
+                    .advertised_addr
+                    .clone()
+                    .unwrap_or_else(|| "localhost".to_string()),
                 port: self.listen_port as i32,
             },
         };
@@ -76,23 +110,39 @@ impl<D: SendableDispatcher> Actor for Server<D> {
         arbiter_spawn!(self.bootstrap.send(event::ServerStarted(addr.clone())));
         arbiter_spawn!(self.peers.send(event::ServerStarted(addr)));
     }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.remove_onion();
+    }
+}
+impl<D: SendableDispatcher> Handler<event::ShuttingDown> for Server<D> {
+    type Result = ();
+    fn handle(&mut self, _: event::ShuttingDown, ctx: &mut Self::Context) {
+        self.remove_onion();
+        ctx.stop();
+    }
 }
 impl<D: SendableDispatcher> StreamHandler<TcpStream, io::Error> for Server<D> {
     fn handle(&mut self, connection: TcpStream, _ctx: &mut Self::Context) {
-        arbiter_spawn!(self.peers.send(event::IncomingConnection(connection)));
+        arbiter_spawn!(self
+            .peers
+            .send(event::IncomingConnection(Box::new(connection))));
     }
 }
 
 pub mod event {
-    use crate::bisq::payload::NodeAddress;
+    use crate::{bisq::payload::NodeAddress, p2p::connection::transport::Duplex};
     use actix::Message;
-    use tokio::net::TcpStream;
 
     pub struct ServerStarted(pub NodeAddress);
     impl Message for ServerStarted {
         type Result = ();
     }
-    pub struct IncomingConnection(pub TcpStream);
+    pub struct ShuttingDown;
+    impl Message for ShuttingDown {
+        type Result = ();
+    }
+    pub struct IncomingConnection(pub Box<dyn Duplex>);
     impl Message for IncomingConnection {
         type Result = ();
     }
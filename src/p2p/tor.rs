@@ -6,7 +6,7 @@ use bufstream::BufStream;
 use rand::Rng;
 use std::{
     convert::TryFrom,
-    fs::{self, File},
+    fs::{self, File, OpenOptions},
     io::{self, BufRead, Read, Write},
     net::{TcpStream, ToSocketAddrs},
     path::PathBuf,
@@ -42,6 +42,7 @@ pub enum TCError {
     UnknownResponse,
     CannotReadAuthCookie,
     AuthenticationError,
+    NoSupportedAuthMethod(Vec<String>),
     TorError(TCErrorKind),
 }
 
@@ -79,16 +80,43 @@ type TCResult<T> = Result<T, TCError>;
 
 pub struct TorControl(BufStream<TcpStream>);
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnionVersion {
+    V2,
+    V3,
+}
+impl OnionVersion {
+    fn key_type(self) -> &'static str {
+        match self {
+            OnionVersion::V2 => "RSA1024",
+            OnionVersion::V3 => "ED25519-V3",
+        }
+    }
+}
+impl FromStr for OnionVersion {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "2" => Ok(OnionVersion::V2),
+            "3" => Ok(OnionVersion::V3),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct AddOnionConfig {
     pub virtual_port: u16,
-    pub target_port: u16,
+    // A port on tor's host or an explicit host:port
+    pub target: String,
     pub private_key_path: PathBuf,
+    pub version: OnionVersion,
 }
 
 #[derive(Debug)]
 pub struct OnionAddr {
     pub port: u16,
+    pub service_id: String,
     pub onion_service: String,
 }
 
@@ -100,16 +128,26 @@ struct ProtocolInfo {
 }
 
 impl TorControl {
-    pub fn connect(addr: impl ToSocketAddrs) -> TCResult<Self> {
-        TorControl(BufStream::new(TcpStream::connect(addr)?)).authenticate()
+    pub fn connect(addr: impl ToSocketAddrs, password: Option<&str>) -> TCResult<Self> {
+        TorControl(BufStream::new(TcpStream::connect(addr)?)).authenticate(password)
     }
 
-    pub fn add_v2_onion(&mut self, conf: AddOnionConfig) -> TCResult<OnionAddr> {
+    pub fn add_onion(&mut self, conf: AddOnionConfig) -> TCResult<OnionAddr> {
+        // A stored key keeps whatever version it was created with
         let key_param = match fs::read_to_string(&conf.private_key_path) {
-            Ok(key) => key,
-            _ => "NEW:RSA1024".into(),
+            Ok(key) => {
+                let key = key.trim().to_string();
+                if !key.starts_with(conf.version.key_type()) {
+                    warn!(
+                        "Onion key {:?} is not a {:?} key, using it anyway",
+                        conf.private_key_path, conf.version
+                    );
+                }
+                key
+            }
+            _ => format!("NEW:{}", conf.version.key_type()),
         };
-        let port_param = format!("Port={},{}", conf.virtual_port, conf.target_port);
+        let port_param = format!("Port={},{}", conf.virtual_port, conf.target);
         send_command(
             &mut self.0,
             format!("ADD_ONION {} {}", key_param, port_param),
@@ -118,7 +156,8 @@ impl TorControl {
         let mut service_id = "";
         let mut private_key = "";
         for section in response.split(" ") {
-            let split: Vec<&str> = section.split("=").collect();
+            // Keys are base64 and may end in '=' padding
+            let split: Vec<&str> = section.splitn(2, "=").collect();
             if split.len() == 2 {
                 match split[0] {
                     "ServiceID" => service_id = split[1],
@@ -127,16 +166,25 @@ impl TorControl {
                 }
             }
         }
+        if service_id == "" {
+            return Err(TCError::UnknownResponse);
+        }
         if private_key != "" {
-            let mut key_file = File::create(conf.private_key_path)?;
+            let mut key_file = key_file_options().open(conf.private_key_path)?;
             key_file.write_all(private_key.as_bytes())?
         }
         Ok(OnionAddr {
             port: conf.virtual_port,
+            service_id: service_id.to_string(),
             onion_service: service_id.to_string() + ".onion",
         })
     }
 
+    pub fn del_onion(&mut self, service_id: &str) -> TCResult<()> {
+        send_command(&mut self.0, format!("DEL_ONION {}", service_id))?;
+        read_lines(&mut self.0).map(|_| ())
+    }
+
     fn protocol_info(&mut self) -> TCResult<ProtocolInfo> {
         send_command(
             &mut self.0,
@@ -147,7 +195,7 @@ impl TorControl {
         let mut auth_methods = "";
         let mut tor_version = "";
         for section in response.split(" ") {
-            let split: Vec<&str> = section.split("=").collect();
+            let split: Vec<&str> = section.splitn(2, "=").collect();
             if split.len() == 2 {
                 match split[0] {
                     "COOKIEFILE" => cookiefile = split[1],
@@ -164,9 +212,24 @@ impl TorControl {
         })
     }
 
-    fn authenticate(mut self) -> TCResult<Self> {
-        let auth_cookie = self.get_auth_cookie()?;
+    fn authenticate(mut self, password: Option<&str>) -> TCResult<Self> {
+        let info = self.protocol_info()?;
+        let supports = |method: &str| info.auth_methods.iter().any(|m| m == method);
+        if supports("NULL") {
+            send_command(&mut self.0, "AUTHENTICATE".into())?;
+            read_lines(&mut self.0).map(|_| self)
+        } else if let (true, Some(password)) = (supports("HASHEDPASSWORD"), password) {
+            send_command(&mut self.0, format!("AUTHENTICATE {}", quote(password)))?;
+            read_lines(&mut self.0).map(|_| self)
+        } else if supports("SAFECOOKIE") {
+            let auth_cookie = read_auth_cookie(&info.cookiefile)?;
+            self.authenticate_safecookie(auth_cookie)
+        } else {
+            Err(TCError::NoSupportedAuthMethod(info.auth_methods))
+        }
+    }
 
+    fn authenticate_safecookie(mut self, auth_cookie: Vec<u8>) -> TCResult<Self> {
         let client_nonce = rand::thread_rng().gen::<[u8; NONCE_LENGTH]>();
         send_command(
             &mut self.0,
@@ -186,10 +249,10 @@ impl TorControl {
                 }
             }
         }
-        let decoded_server_hash = FromStr::from_str(serverhash)
-            .expect("Could not decode serverhash during Authentication");
-        let decoded_server_nonce: Vec<u8> = FromHex::from_hex(servernonce)
-            .expect("Could not decode servernonce during Authentication");
+        let decoded_server_hash: Hmac<sha256::Hash> =
+            FromStr::from_str(serverhash).map_err(|_| TCError::UnknownResponse)?;
+        let decoded_server_nonce: Vec<u8> =
+            FromHex::from_hex(servernonce).map_err(|_| TCError::UnknownResponse)?;
 
         let mut message = Vec::new();
         message.extend(auth_cookie);
@@ -215,19 +278,24 @@ impl TorControl {
 
         read_lines(&mut self.0).map(|_| self)
     }
+}
 
-    fn get_auth_cookie(&mut self) -> TCResult<Vec<u8>> {
-        let info = self.protocol_info()?;
-        let mut file_content = Vec::new();
-        let length = File::open(info.cookiefile)?.read_to_end(&mut file_content)?;
-        if length != COOKIE_LENGTH {
-            Err(TCError::CannotReadAuthCookie)
-        } else {
-            Ok(file_content)
-        }
+fn read_auth_cookie(cookiefile: &str) -> TCResult<Vec<u8>> {
+    let mut file_content = Vec::new();
+    let length = File::open(cookiefile)
+        .and_then(|mut file| file.read_to_end(&mut file_content))
+        .map_err(|_| TCError::CannotReadAuthCookie)?;
+    if length != COOKIE_LENGTH {
+        Err(TCError::CannotReadAuthCookie)
+    } else {
+        Ok(file_content)
     }
 }
 
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 fn send_command(writer: &mut impl Write, command: String) -> Result<(), io::Error> {
     write!(writer, "{}\r\n", command)?;
     writer.flush()
@@ -288,3 +356,231 @@ fn handle_code(status: u32) -> TCResult<()> {
             .unwrap_or(UnknownResponse)),
     }
 }
+
+// Whoever can read the key can impersonate the hidden service
+fn key_file_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        net::{SocketAddr, TcpListener},
+        thread::{self, JoinHandle},
+    };
+
+    // Answers each expected command with a canned response, like a tor control port would.
+    // An expected command ending in '*' only has to match up to there.
+    fn fake_control_port(script: Vec<(&str, &str)>) -> (SocketAddr, JoinHandle<()>) {
+        let script: Vec<(String, String)> = script
+            .into_iter()
+            .map(|(expected, response)| (expected.to_string(), response.to_string()))
+            .collect();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = BufStream::new(stream);
+            for (expected, response) in script {
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                match expected.ends_with('*') {
+                    true => assert!(line.starts_with(expected.trim_end_matches('*'))),
+                    false => assert_eq!(line.trim_end(), expected),
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                stream.flush().unwrap();
+            }
+        });
+        (addr, handle)
+    }
+
+    fn unauthenticated(addr: SocketAddr) -> TorControl {
+        TorControl(BufStream::new(TcpStream::connect(addr).unwrap()))
+    }
+
+    fn key_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "risq-tor-test-{}-{}",
+            name,
+            rand::thread_rng().gen::<u32>()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn add_new_v3_onion() {
+        let (addr, server) = fake_control_port(vec![(
+            "ADD_ONION NEW:ED25519-V3 Port=9999,5000",
+            "250-ServiceID=vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd\r\n\
+             250-PrivateKey=ED25519-V3:aGVsbG8gd29ybGQ=\r\n\
+             250 OK\r\n",
+        )]);
+        let private_key_path = key_path("v3");
+        let onion = unauthenticated(addr)
+            .add_onion(AddOnionConfig {
+                virtual_port: 9999,
+                target: "5000".into(),
+                private_key_path: private_key_path.clone(),
+                version: OnionVersion::V3,
+            })
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(
+            onion.onion_service,
+            "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion"
+        );
+        assert_eq!(onion.port, 9999);
+        assert_eq!(
+            fs::read_to_string(&private_key_path).unwrap(),
+            "ED25519-V3:aGVsbG8gd29ybGQ="
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&private_key_path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_file(private_key_path).unwrap();
+    }
+
+    #[test]
+    fn add_onion_with_stored_v2_key() {
+        let private_key_path = key_path("v2");
+        fs::write(&private_key_path, "RSA1024:c3RvcmVkIGtleQ==\n").unwrap();
+        let (addr, server) = fake_control_port(vec![(
+            "ADD_ONION RSA1024:c3RvcmVkIGtleQ== Port=9999,10.0.0.2:5000",
+            "250-ServiceID=5quyxpxheyvzmb2d\r\n250 OK\r\n",
+        )]);
+        let onion = unauthenticated(addr)
+            .add_onion(AddOnionConfig {
+                virtual_port: 9999,
+                target: "10.0.0.2:5000".into(),
+                private_key_path: private_key_path.clone(),
+                version: OnionVersion::V2,
+            })
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(onion.service_id, "5quyxpxheyvzmb2d");
+        assert_eq!(
+            fs::read_to_string(&private_key_path).unwrap(),
+            "RSA1024:c3RvcmVkIGtleQ==\n"
+        );
+        fs::remove_file(private_key_path).unwrap();
+    }
+
+    #[test]
+    fn del_onion() {
+        let (addr, server) = fake_control_port(vec![("DEL_ONION 5quyxpxheyvzmb2d", "250 OK\r\n")]);
+        unauthenticated(addr).del_onion("5quyxpxheyvzmb2d").unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn tor_error() {
+        let (addr, server) = fake_control_port(vec![(
+            "DEL_ONION unknown",
+            "552 Unknown Onion Service id\r\n",
+        )]);
+        match unauthenticated(addr).del_onion("unknown") {
+            Err(TCError::TorError(TCErrorKind::UnrecognizedEntity)) => (),
+            res => panic!("Expected UnrecognizedEntity got {:?}", res),
+        }
+        server.join().unwrap();
+    }
+
+    const PROTOCOL_INFO: &str = "PROTOCOLINFO 1";
+
+    fn protocol_info_response(methods: &str, cookiefile: &str) -> String {
+        format!(
+            "250-PROTOCOLINFO 1\r\n\
+             250-AUTH METHODS={} COOKIEFILE=\"{}\"\r\n\
+             250-VERSION Tor=\"0.4.2.5\"\r\n\
+             250 OK\r\n",
+            methods, cookiefile
+        )
+    }
+
+    #[test]
+    fn null_auth() {
+        let (addr, server) = fake_control_port(vec![
+            (PROTOCOL_INFO, &protocol_info_response("NULL", "")),
+            ("AUTHENTICATE", "250 OK\r\n"),
+        ]);
+        TorControl::connect(addr, None).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn hashed_password_auth() {
+        let (addr, server) = fake_control_port(vec![
+            (PROTOCOL_INFO, &protocol_info_response("HASHEDPASSWORD", "")),
+            (r#"AUTHENTICATE "pass \"word\"""#, "250 OK\r\n"),
+        ]);
+        TorControl::connect(addr, Some(r#"pass "word""#)).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn bad_password() {
+        let (addr, server) = fake_control_port(vec![
+            (PROTOCOL_INFO, &protocol_info_response("HASHEDPASSWORD", "")),
+            (r#"AUTHENTICATE "wrong""#, "515 Authentication failed\r\n"),
+        ]);
+        match TorControl::connect(addr, Some("wrong")) {
+            Err(TCError::TorError(TCErrorKind::BadAuth)) => (),
+            res => panic!("Expected BadAuth got {:?}", res.map(|_| ())),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn no_supported_auth_method() {
+        let (addr, server) = fake_control_port(vec![(
+            PROTOCOL_INFO,
+            &protocol_info_response("HASHEDPASSWORD", ""),
+        )]);
+        match TorControl::connect(addr, None) {
+            Err(TCError::NoSupportedAuthMethod(methods)) => {
+                assert_eq!(methods, vec!["HASHEDPASSWORD".to_string()])
+            }
+            res => panic!("Expected NoSupportedAuthMethod got {:?}", res.map(|_| ())),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn safecookie_with_undecodable_server_hash() {
+        let cookiefile = key_path("cookie");
+        fs::write(&cookiefile, [7u8; COOKIE_LENGTH]).unwrap();
+        let (addr, server) = fake_control_port(vec![
+            (
+                PROTOCOL_INFO,
+                &protocol_info_response("COOKIE,SAFECOOKIE", cookiefile.to_str().unwrap()),
+            ),
+            (
+                "AUTHCHALLENGE SAFECOOKIE *",
+                "250 AUTHCHALLENGE SERVERHASH=nothex SERVERNONCE=00\r\n",
+            ),
+        ]);
+        match TorControl::connect(addr, None) {
+            Err(TCError::UnknownResponse) => (),
+            res => panic!("Expected UnknownResponse got {:?}", res.map(|_| ())),
+        }
+        server.join().unwrap();
+        fs::remove_file(cookiefile).unwrap();
+    }
+}